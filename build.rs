@@ -0,0 +1,254 @@
+// generates the opcode tables and the cpu's dispatch match from ops.txt, so that instruction
+// timing and flag effects come from a single place.
+//
+// ops.txt lists every defined unprefixed opcode in order as blocks of four lines:
+//     mnemonic            e.g. "JR NZ,r8"
+//     length  cycles      e.g. "2  12/8", cycles are in clock ticks, taken/not taken
+//     flags               e.g. "Z 0 H -", one of - 0 1 or the flag's name for Z N H C
+//     (empty line)
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+// opcodes that don't exist on the gameboy and are left out of ops.txt
+const UNDEFINED_OPS: [u8; 11] = [0xd3, 0xdb, 0xdd, 0xe3, 0xe4, 0xeb, 0xec, 0xed, 0xf4, 0xfc, 0xfd];
+
+const CB_OPS: [&str; 8] = ["RLC", "RRC", "RL", "RR", "SLA", "SRA", "SWAP", "SRL"];
+const CB_OPERANDS: [&str; 8] = ["B", "C", "D", "E", "H", "L", "(HL)", "A"];
+
+struct Op {
+    mnemonic: String,
+    length: u8,
+    cycles: u8,         // machine cycles, branch not taken
+    branch_cycles: u8,  // machine cycles, branch taken
+    flags: Vec<String>,
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=ops.txt");
+    println!("cargo:rerun-if-changed=build.rs");
+
+    let ops_txt = fs::read_to_string("ops.txt").expect("couldn't read ops.txt");
+    let ops = parse_ops(&ops_txt);
+
+    let out_dir = env::var("OUT_DIR").unwrap();
+    fs::write(Path::new(&out_dir).join("opcodes.rs"), gen_tables(&ops)).unwrap();
+    fs::write(Path::new(&out_dir).join("dispatch.rs"), gen_dispatch(&ops)).unwrap();
+}
+
+// parse ops.txt into a table indexed by opcode, undefined opcodes are None
+fn parse_ops(text: &str) -> Vec<Option<Op>> {
+    let mut lines = text.lines().map(str::trim).filter(|l| !l.is_empty());
+    let mut ops = Vec::new();
+
+    for opcode in 0..=0xffu8 {
+        if UNDEFINED_OPS.contains(&opcode) {
+            ops.push(None);
+            continue;
+        }
+
+        let mnemonic = lines.next().unwrap_or_else(|| panic!("ops.txt ends before {:#04x}", opcode));
+        let timing: Vec<&str> = lines.next().unwrap().split_whitespace().collect();
+        let flags = lines.next().unwrap().split_whitespace().map(String::from).collect::<Vec<_>>();
+        assert_eq!(flags.len(), 4, "bad flags for {:#04x} in ops.txt", opcode);
+
+        // cycles are either "n" or "taken/not taken" in clock ticks
+        let ticks: Vec<u8> = timing[1].split('/').map(|t| t.parse().unwrap()).collect();
+        ops.push(Some(Op {
+            mnemonic: mnemonic.to_string(),
+            length: timing[0].parse().unwrap(),
+            cycles: ticks[ticks.len() - 1] / 4,
+            branch_cycles: ticks[0] / 4,
+            flags,
+        }));
+    }
+
+    ops
+}
+
+// the cb-prefixed ops follow a regular grid so they are generated rather than listed
+fn cb_ops() -> Vec<Op> {
+    (0..=0xffu8).map(|opcode| {
+        let operand = CB_OPERANDS[(opcode & 0x07) as usize];
+        let bit = (opcode >> 3) & 0x07;
+        let (mnemonic, flags) = match opcode >> 6 {
+            0 if bit == 6 => (format!("SWAP {}", operand), "Z 0 0 0"),
+            0 => (format!("{} {}", CB_OPS[bit as usize], operand), "Z 0 0 C"),
+            1 => (format!("BIT {},{}", bit, operand), "Z 0 1 -"),
+            2 => (format!("RES {},{}", bit, operand), "- - - -"),
+            _ => (format!("SET {},{}", bit, operand), "- - - -"),
+        };
+
+        // (HL) costs an extra read, and an extra write for everything except bit
+        let cycles = match (operand, opcode >> 6) {
+            ("(HL)", 1) => 3,
+            ("(HL)", _) => 4,
+            _ => 2,
+        };
+
+        Op {
+            mnemonic,
+            length: 2,
+            cycles,
+            branch_cycles: cycles,
+            flags: flags.split_whitespace().map(String::from).collect(),
+        }
+    }).collect()
+}
+
+fn gen_op_info(op: &Op) -> String {
+    let flags: Vec<&str> = op.flags.iter().map(|f| match f.as_str() {
+        "-" => "FlagEffect::Unchanged",
+        "0" => "FlagEffect::Reset",
+        "1" => "FlagEffect::Set",
+        _ => "FlagEffect::Affected",
+    }).collect();
+
+    format!(
+        "OpInfo {{ mnemonic: {:?}, length: {}, cycles: {}, branch_cycles: {}, flags: [{}] }}",
+        op.mnemonic, op.length, op.cycles, op.branch_cycles, flags.join(", ")
+    )
+}
+
+fn gen_tables(ops: &[Option<Op>]) -> String {
+    let mut out = String::from("// generated by build.rs from ops.txt, do not edit\n\n");
+
+    out += "pub static OPCODES: [Option<OpInfo>; 256] = [\n";
+    for (opcode, op) in ops.iter().enumerate() {
+        match op {
+            Some(op) => out += &format!("    Some({}),  // {:#04x}\n", gen_op_info(op), opcode),
+            None => out += &format!("    None,  // {:#04x}\n", opcode),
+        }
+    }
+    out += "];\n\n";
+
+    out += "pub static CB_OPCODES: [OpInfo; 256] = [\n";
+    for (opcode, op) in cb_ops().iter().enumerate() {
+        out += &format!("    {},  // 0xcb {:#04x}\n", gen_op_info(op), opcode);
+    }
+    out += "];\n";
+
+    out
+}
+
+// expression reading an 8 bit operand, any code needed before it goes into pre
+fn gen_read(operand: &str, pre: &mut String, post: &mut String) -> String {
+    match operand {
+        "A" | "B" | "C" | "D" | "E" | "H" | "L" => format!("self.regs.{}()", operand.to_lowercase()),
+        "d8" => { *pre += "let data = self.fetch_ins_byte(); "; "data".to_string() },
+        _ => {
+            let addr = gen_addr(operand, pre, post);
+            *pre += &format!("let data = self.read_cycle({}); ", addr);
+            "data".to_string()
+        },
+    }
+}
+
+// expression for the address of a memory operand such as (HL+) or (a16)
+fn gen_addr(operand: &str, pre: &mut String, post: &mut String) -> String {
+    match &operand[1..operand.len() - 1] {
+        "BC" | "DE" | "HL" => format!("self.regs.{}()", operand[1..3].to_lowercase()),
+        "HL+" => { *post += "self.regs.set_hl(self.regs.hl().wrapping_add(1)); "; "self.regs.hl()".to_string() },
+        "HL-" => { *post += "self.regs.set_hl(self.regs.hl().wrapping_sub(1)); "; "self.regs.hl()".to_string() },
+        "C" => "self.regs.c() as u16 + 0xff00".to_string(),
+        "a8" => { *pre += "let addr = self.fetch_ins_byte() as u16 + 0xff00; "; "addr".to_string() },
+        "a16" => { *pre += "let addr = self.fetch_ins_word(); "; "addr".to_string() },
+        inner => panic!("unknown memory operand ({})", inner),
+    }
+}
+
+fn gen_cond(operand: &str) -> String {
+    format!("Some(Condition::{})", operand)
+}
+
+// translate a single instruction into the rust that executes it. returns the statements before
+// the handler call, the call itself, the statements after it and whether the call is a
+// conditional branch whose result picks the cycle count
+fn gen_exec(opcode: u8, op: &Op) -> (String, String, String, bool) {
+    let mut parts = op.mnemonic.split_whitespace();
+    let name = parts.next().unwrap();
+    let operands: Vec<&str> = parts.next().map(|o| o.split(',').collect()).unwrap_or_default();
+    let is_reg16 = |o: &str| ["BC", "DE", "HL", "SP", "AF"].contains(&o);
+
+    let mut pre = String::new();
+    let mut post = String::new();
+    let mut branch = false;
+
+    let call = match (name, operands.as_slice()) {
+        ("NOP", _) => String::new(),
+        ("PREFIX", _) => "self.decode_cb()".to_string(),
+        ("LD", ["HL", "SP+r8"]) => { pre += "let data = self.fetch_ins_byte(); "; "self.ld_hl_sp_offset(data);".to_string() },
+        ("LD", [dest, "d16"]) => { pre += "let data = self.fetch_ins_word(); "; format!("self.ld_word(data, Some(RegIndex::{}), 0);", dest) },
+        ("LD", ["(a16)", "SP"]) => { pre += "let addr = self.fetch_ins_word(); "; "self.ld_word(self.regs.sp(), None, addr);".to_string() },
+        ("LD", ["SP", "HL"]) => "self.ld_sp_hl();".to_string(),
+        ("LD", [dest, src]) if is_reg16(dest) => format!("self.ld_word(self.regs.{}(), Some(RegIndex::{}), 0);", src.to_lowercase(), dest),
+        ("LD", [dest, src]) | ("LDH", [dest, src]) => {
+            let data = gen_read(src, &mut pre, &mut post);
+            if dest.starts_with('(') {
+                let addr = gen_addr(dest, &mut pre, &mut post);
+                format!("self.ld_byte({}, None, {});", data, addr)
+            } else {
+                format!("self.ld_byte({}, Some(RegIndex::{}), 0);", data, dest)
+            }
+        },
+        ("INC", [r]) | ("DEC", [r]) => {
+            let func = name.to_lowercase();
+            if r.starts_with('(') {
+                format!("self.{}(None, self.regs.hl());", func)
+            } else {
+                format!("self.{}(Some(RegIndex::{}), 0);", func, r)
+            }
+        },
+        ("ADD", ["SP", _]) => { pre += "let data = self.fetch_ins_byte(); "; "self.add_sp(data);".to_string() },
+        ("ADD", ["HL", src]) => format!("self.add_word(self.regs.{}(), RegIndex::HL);", src.to_lowercase()),
+        ("ADD", ["A", src]) | ("ADC", ["A", src]) => {
+            let data = gen_read(src, &mut pre, &mut post);
+            format!("self.add_byte({}, {});", data, name == "ADC")
+        },
+        ("SUB", [src]) | ("SBC", ["A", src]) => {
+            let data = gen_read(src, &mut pre, &mut post);
+            format!("self.sub_byte({}, {});", data, name == "SBC")
+        },
+        ("AND", [src]) | ("XOR", [src]) | ("OR", [src]) | ("CP", [src]) => {
+            let data = gen_read(src, &mut pre, &mut post);
+            format!("self.{}({});", name.to_lowercase(), data)
+        },
+        ("JR", [_]) => "self.jr(None);".to_string(),
+        ("JR", [cond, _]) => { branch = true; format!("self.jr({})", gen_cond(cond)) },
+        ("JP", ["(HL)"]) => "self.jp_hl();".to_string(),
+        ("JP", [_]) => { pre += "let addr = self.fetch_ins_word(); "; "self.jump(addr, None);".to_string() },
+        ("JP", [cond, _]) => { branch = true; pre += "let addr = self.fetch_ins_word(); "; format!("self.jump(addr, {})", gen_cond(cond)) },
+        ("CALL", [_]) => "self.call(None);".to_string(),
+        ("CALL", [cond, _]) => { branch = true; format!("self.call({})", gen_cond(cond)) },
+        ("RET", []) => "self.ret(None);".to_string(),
+        ("RET", [cond]) => { branch = true; format!("self.ret({})", gen_cond(cond)) },
+        ("PUSH", [r]) => format!("self.push(self.regs.{}());", r.to_lowercase()),
+        ("POP", [r]) => format!("self.pop(RegIndex::{});", r),
+        ("RST", [addr]) => format!("self.rst(0x{});", addr.trim_end_matches('H')),
+        (_, _) => format!("self.{}();", name.to_lowercase()),
+    };
+
+    assert!(post.is_empty() || !branch, "{:#04x} can't both branch and update HL", opcode);
+    (pre, call, post, branch)
+}
+
+fn gen_dispatch(ops: &[Option<Op>]) -> String {
+    let mut out = String::from("// generated by build.rs from ops.txt, do not edit\nmatch op {\n");
+
+    for (opcode, op) in ops.iter().enumerate() {
+        let opcode = opcode as u8;
+        let arm = match op {
+            None => "self.undefined_op(op); 1".to_string(),
+            Some(op) if op.mnemonic == "PREFIX CB" => "self.decode_cb()".to_string(),
+            Some(op) => match gen_exec(opcode, op) {
+                (pre, cond, _, true) => format!("{}if {} {{ {} }} else {{ {} }}", pre, cond, op.branch_cycles, op.cycles),
+                (pre, call, post, false) => format!("{}{} {}{}", pre, call, post, op.cycles).trim_start().to_string(),
+            },
+        };
+        out += &format!("    {:#04x} => {{ {} }},\n", opcode, arm);
+    }
+
+    out += "}\n";
+    out
+}
@@ -0,0 +1,77 @@
+// sound registers FF10-FF3F. no sound is generated yet, but the registers behave like the real
+// ones so games see what they expect when they read them back
+
+// bits that always read as 1 in each register from FF10 to FF2F, including the unused ones
+const READ_MASKS: [u8; 0x20] = [
+    0x80, 0x3f, 0x00, 0xff, 0xbf,   // NR10-NR14
+    0xff, 0x3f, 0x00, 0xff, 0xbf,   // unused, NR21-NR24
+    0x7f, 0xff, 0x9f, 0xff, 0xbf,   // NR30-NR34
+    0xff, 0xff, 0x00, 0x00, 0xbf,   // unused, NR41-NR44
+    0x00, 0x00, 0x70,               // NR50-NR52
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+];
+
+pub struct Apu {
+    regs: [u8; 0x20],       // FF10-FF2F as written
+    wave_ram: [u8; 0x10],   // FF30-FF3F
+    enabled: bool,          // NR52 bit 7, everything but wave ram is cleared and locked while off
+    channels_on: u8,        // NR52 bits 0-3
+}
+
+impl Apu {
+    pub fn init() -> Apu {
+        Apu {
+            regs: [0; 0x20],
+            wave_ram: [0; 0x10],
+            enabled: false,
+            channels_on: 0,
+        }
+    }
+
+    // whether the dac of channel 1-4 is on, without it the channel can't be triggered
+    fn dac_on(&self, channel: usize) -> bool {
+        match channel {
+            2 => self.regs[0x0a] & 0x80 != 0,       // NR30
+            _ => self.regs[channel * 5 + 2] & 0xf8 != 0,   // volume and envelope direction
+        }
+    }
+
+    pub fn read_reg(&self, addr: u16) -> u8 {
+        match addr {
+            0xff26 => READ_MASKS[0x16] | (self.enabled as u8) << 7 | self.channels_on,
+            0xff10..=0xff2f => {
+                let i = (addr - 0xff10) as usize;
+                self.regs[i] | READ_MASKS[i]
+            },
+            0xff30..=0xff3f => self.wave_ram[(addr - 0xff30) as usize],
+            _ => 0xff,
+        }
+    }
+
+    pub fn write_reg(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xff26 => {
+                self.enabled = val & 0x80 != 0;
+                if !self.enabled {
+                    self.regs = [0; 0x20];
+                    self.channels_on = 0;
+                }
+            },
+            0xff10..=0xff2f if self.enabled => {
+                let i = (addr - 0xff10) as usize;
+                self.regs[i] = val;
+
+                if i < 0x14 {
+                    let channel = i / 5;
+                    if !self.dac_on(channel) {
+                        self.channels_on &= !(1 << channel);
+                    } else if i % 5 == 4 && val & 0x80 != 0 {
+                        self.channels_on |= 1 << channel;   // NRx4 bit 7 triggers the channel
+                    }
+                }
+            },
+            0xff30..=0xff3f => self.wave_ram[(addr - 0xff30) as usize] = val,
+            _ => (),
+        }
+    }
+}
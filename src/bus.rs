@@ -0,0 +1,130 @@
+use crate::interrupts::Interrupt;
+
+// memory bus seen by the cpu, implemented by the MMU for the real system
+pub trait Bus {
+    fn read_byte(&mut self, addr: u16) -> u8;
+    fn write_byte(&mut self, addr: u16, data: u8);
+
+    // advance the rest of the system by the given number of machine cycles
+    fn tick(&mut self, cycles: u8);
+
+    // interrupts that are both requested (IF) and enabled (IE). the cpu checks these
+    // internally, so unlike read_byte this is not a memory access
+    fn pending_interrupts(&mut self) -> u8 {
+        self.read_byte(0xffff) & self.read_byte(0xff0f) & 0x1f
+    }
+
+    // clear the request flag of an interrupt that is being serviced
+    fn ack_interrupt(&mut self, intr: Interrupt) {
+        let flags = self.read_byte(0xff0f);
+        self.write_byte(0xff0f, flags & !intr.mask());
+    }
+
+    // whether a boot rom is overlaid on the start of memory, in which case the cpu starts
+    // executing it from 0 instead of at the cartridge entry point
+    fn boot_rom_mapped(&self) -> bool {
+        false
+    }
+
+    // bank mapped at the given address, for error reports and debuggers
+    fn bank(&self, _addr: u16) -> u16 {
+        0
+    }
+
+    fn read_word(&mut self, addr: u16) -> u16 {
+        let lo = self.read_byte(addr);
+        let hi = self.read_byte(addr.wrapping_add(1));
+        ((hi as u16) << 8) | (lo as u16)      // little endian
+    }
+
+    fn write_word(&mut self, addr: u16, data: u16) {
+        self.write_byte(addr, (data & 0x00ff) as u8);        // little endian
+        self.write_byte(addr.wrapping_add(1), (data >> 8) as u8);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+// a single memory access made by the cpu
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BusAccess {
+    pub addr: u16,
+    pub data: u8,
+    pub kind: Access,
+}
+
+// flat 64K of ram with nothing mapped into it, for testing instructions and for tooling
+pub struct FlatBus {
+    memory: Vec<u8>,
+    cycles: u64,    // machine cycles ticked so far
+    access: Option<BusAccess>,  // access made during the current machine cycle
+
+    // the access made in each machine cycle since tracing was enabled, None for cycles where
+    // the cpu was busy internally
+    trace: Option<Vec<Option<BusAccess>>>,
+}
+
+impl FlatBus {
+    pub fn init() -> FlatBus {
+        FlatBus {
+            memory: vec![0; 0x10000],
+            cycles: 0,
+            access: None,
+            trace: None,
+        }
+    }
+
+    // start recording the access of every machine cycle, clearing any previous recording
+    pub fn enable_trace(&mut self) {
+        self.access = None;
+        self.trace = Some(Vec::new());
+    }
+
+    pub fn trace(&self) -> &[Option<BusAccess>] {
+        self.trace.as_deref().unwrap_or(&[])
+    }
+
+    // copy given bytes into memory starting at addr
+    pub fn load(&mut self, addr: u16, data: &[u8]) {
+        for (i, byte) in data.iter().enumerate() {
+            self.memory[(addr as usize + i) & 0xffff] = *byte;
+        }
+    }
+
+    pub fn cycles(&self) -> u64 { self.cycles }
+}
+
+impl Bus for FlatBus {
+    fn read_byte(&mut self, addr: u16) -> u8 {
+        let data = self.memory[addr as usize];
+        self.access = Some(BusAccess { addr, data, kind: Access::Read });
+        data
+    }
+
+    fn write_byte(&mut self, addr: u16, data: u8) {
+        self.memory[addr as usize] = data;
+        self.access = Some(BusAccess { addr, data, kind: Access::Write });
+    }
+
+    fn pending_interrupts(&mut self) -> u8 {
+        self.memory[0xffff] & self.memory[0xff0f] & 0x1f
+    }
+
+    fn ack_interrupt(&mut self, intr: Interrupt) {
+        self.memory[0xff0f] &= !intr.mask();
+    }
+
+    fn tick(&mut self, cycles: u8) {
+        self.cycles += cycles as u64;
+        for _ in 0..cycles {
+            let access = self.access.take();
+            if let Some(trace) = &mut self.trace {
+                trace.push(access);
+            }
+        }
+    }
+}
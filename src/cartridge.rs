@@ -0,0 +1,339 @@
+// cartridge header at 0100-014F of every rom, which tells the system what hardware is inside the
+// cartridge and which gameboy models the game supports
+
+use crate::model::Model;
+
+// the logo every cartridge has to contain at 0104-0133, checked by the boot rom
+pub const NINTENDO_LOGO: [u8; 48] = [
+    0xce, 0xed, 0x66, 0x66, 0xcc, 0x0d, 0x00, 0x0b, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0c, 0x00, 0x0d,
+    0x00, 0x08, 0x11, 0x1f, 0x88, 0x89, 0x00, 0x0e, 0xdc, 0xcc, 0x6e, 0xe6, 0xdd, 0xdd, 0xd9, 0x99,
+    0xbb, 0xbb, 0x67, 0x63, 0x6e, 0x0e, 0xec, 0xcc, 0xdd, 0xdc, 0x99, 0x9f, 0xbb, 0xb9, 0x33, 0x3e,
+];
+
+// memory bank controller on the cartridge
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MapperKind {
+    RomOnly,
+    Mbc1,
+    Mbc2,
+    Mbc3,
+    Mbc5,
+    Mbc6,
+    Mbc7,
+    Mmm01,
+    PocketCamera,
+    Tama5,
+    HuC1,
+    HuC3,
+    Unknown(u8),
+}
+
+// cartridge type byte at 0147, the mapper plus whatever else is on the cartridge
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CartridgeType {
+    pub code: u8,
+    pub mapper: MapperKind,
+    pub ram: bool,
+    pub battery: bool,  // ram (and clock) are kept when the gameboy is turned off
+    pub timer: bool,    // mbc3 real time clock
+    pub rumble: bool,
+}
+
+impl CartridgeType {
+    pub fn from_code(code: u8) -> CartridgeType {
+        use MapperKind::*;
+        let (mapper, ram, battery, timer, rumble) = match code {
+            0x00 => (RomOnly, false, false, false, false),
+            0x01 => (Mbc1, false, false, false, false),
+            0x02 => (Mbc1, true, false, false, false),
+            0x03 => (Mbc1, true, true, false, false),
+            0x05 => (Mbc2, true, false, false, false),      // mbc2 has ram built in
+            0x06 => (Mbc2, true, true, false, false),
+            0x08 => (RomOnly, true, false, false, false),
+            0x09 => (RomOnly, true, true, false, false),
+            0x0b => (Mmm01, false, false, false, false),
+            0x0c => (Mmm01, true, false, false, false),
+            0x0d => (Mmm01, true, true, false, false),
+            0x0f => (Mbc3, false, true, true, false),
+            0x10 => (Mbc3, true, true, true, false),
+            0x11 => (Mbc3, false, false, false, false),
+            0x12 => (Mbc3, true, false, false, false),
+            0x13 => (Mbc3, true, true, false, false),
+            0x19 => (Mbc5, false, false, false, false),
+            0x1a => (Mbc5, true, false, false, false),
+            0x1b => (Mbc5, true, true, false, false),
+            0x1c => (Mbc5, false, false, false, true),
+            0x1d => (Mbc5, true, false, false, true),
+            0x1e => (Mbc5, true, true, false, true),
+            0x20 => (Mbc6, true, true, false, false),
+            0x22 => (Mbc7, true, true, false, true),
+            0xfc => (PocketCamera, true, true, false, false),
+            0xfd => (Tama5, true, true, true, false),
+            0xfe => (HuC3, true, true, true, false),
+            0xff => (HuC1, true, true, false, false),
+            _ => (Unknown(code), false, false, false, false),
+        };
+        CartridgeType { code, mapper, ram, battery, timer, rumble }
+    }
+}
+
+// how a game uses the color gameboy, from 0143
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CgbSupport {
+    None,
+    Compatible,     // enhanced on cgb but also runs on older models
+    Only,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Destination {
+    Japan,
+    Overseas,
+}
+
+// who published the game. old cartridges have a 1 byte code at 014B, newer ones put 0x33 there
+// and a 2 character code at 0144-0145
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Licensee {
+    Old(u8),
+    New([u8; 2]),
+}
+
+impl Licensee {
+    // published by nintendo itself
+    pub fn is_nintendo(self) -> bool {
+        self == Licensee::Old(0x01) || self == Licensee::New(*b"01")
+    }
+}
+
+pub struct Cartridge {
+    title: String,
+    manufacturer: Option<String>,   // 4 character code in the end of the title on newer games
+    cgb: CgbSupport,
+    sgb: bool,                      // uses super gameboy features
+    licensee: Licensee,
+    cart_type: CartridgeType,
+    rom_size: usize,                // bytes
+    ram_size: usize,                // bytes of external ram, not counting mbc2's built in ram
+    destination: Destination,
+    version: u8,
+    logo_valid: bool,
+    header_checksum: u8,
+    header_checksum_valid: bool,
+    global_checksum: u16,
+    global_checksum_valid: bool,
+}
+
+impl Cartridge {
+    // parse the header of a rom. anything past the end of the rom reads as 0
+    pub fn parse(rom: &[u8]) -> Cartridge {
+        let byte = |addr: usize| rom.get(addr).copied().unwrap_or(0);
+
+        let cgb = match byte(0x143) {
+            0x80 => CgbSupport::Compatible,
+            0xc0 => CgbSupport::Only,
+            _ => CgbSupport::None,
+        };
+
+        // the title used to be 16 characters long, but color games took the last one for the
+        // cgb flag and later the 4 before it for the manufacturer code
+        let mut title_end = if cgb == CgbSupport::None { 0x144 } else { 0x143 };
+        let mut manufacturer = None;
+        if cgb != CgbSupport::None {
+            let code: Vec<u8> = (0x13f..0x143).map(byte).collect();
+            if code.iter().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit()) {
+                manufacturer = Some(String::from_utf8_lossy(&code).into_owned());
+                title_end = 0x13f;
+            }
+        }
+        let title: String = (0x134..title_end)
+            .map(byte)
+            .take_while(|c| *c != 0)
+            .map(|c| if c.is_ascii_graphic() || c == b' ' { c as char } else { '?' })
+            .collect();
+
+        let old_licensee = byte(0x14b);
+        let licensee = if old_licensee == 0x33 {
+            Licensee::New([byte(0x144), byte(0x145)])
+        } else {
+            Licensee::Old(old_licensee)
+        };
+
+        // sizes double from 32KB, except for 3 codes that were listed for 72, 80 and 96 banks
+        let rom_size = match byte(0x148) {
+            0x52 => 72 * 0x4000,
+            0x53 => 80 * 0x4000,
+            0x54 => 96 * 0x4000,
+            code => 0x8000 << (code & 0x0f),
+        };
+
+        let ram_size = match byte(0x149) {
+            0x02 => 0x2000,
+            0x03 => 0x8000,
+            0x04 => 0x20000,
+            0x05 => 0x10000,
+            _ => 0,
+        };
+
+        let header_checksum = Cartridge::compute_header_checksum(rom);
+        let global_checksum = rom.iter()
+            .enumerate()
+            .filter(|(addr, _)| *addr != 0x14e && *addr != 0x14f)
+            .fold(0u16, |sum, (_, b)| sum.wrapping_add(*b as u16));
+
+        Cartridge {
+            title: title.trim_end().to_string(),
+            manufacturer,
+            cgb,
+            sgb: byte(0x146) == 0x03,
+            licensee,
+            cart_type: CartridgeType::from_code(byte(0x147)),
+            rom_size,
+            ram_size,
+            destination: if byte(0x14a) == 0 { Destination::Japan } else { Destination::Overseas },
+            version: byte(0x14c),
+            logo_valid: (0..48).all(|i| byte(0x104 + i) == NINTENDO_LOGO[i]),
+            header_checksum: byte(0x14d),
+            header_checksum_valid: header_checksum == byte(0x14d),
+            global_checksum: (byte(0x14e) as u16) << 8 | byte(0x14f) as u16,
+            global_checksum_valid: global_checksum == ((byte(0x14e) as u16) << 8 | byte(0x14f) as u16),
+        }
+    }
+
+    // the checksum the header at 0134-014C of a rom should have
+    pub fn compute_header_checksum(rom: &[u8]) -> u8 {
+        (0x134..=0x14c).fold(0u8, |x, addr| x.wrapping_sub(rom.get(addr).copied().unwrap_or(0)).wrapping_sub(1))
+    }
+
+    pub fn title(&self) -> &str { &self.title }
+    pub fn manufacturer(&self) -> Option<&str> { self.manufacturer.as_deref() }
+    pub fn cgb(&self) -> CgbSupport { self.cgb }
+    pub fn licensee(&self) -> Licensee { self.licensee }
+    pub fn cart_type(&self) -> CartridgeType { self.cart_type }
+    pub fn rom_size(&self) -> usize { self.rom_size }
+    pub fn ram_size(&self) -> usize { self.ram_size }
+    pub fn destination(&self) -> Destination { self.destination }
+    pub fn version(&self) -> u8 { self.version }
+
+    // the sgb only enables its features for games that also use the new licensee code
+    pub fn sgb(&self) -> bool {
+        self.sgb && matches!(self.licensee, Licensee::New(_))
+    }
+
+    // the most capable model the game makes use of
+    pub fn preferred_model(&self) -> Model {
+        if self.cgb != CgbSupport::None {
+            Model::Cgb
+        } else if self.sgb() {
+            Model::Sgb
+        } else {
+            Model::Dmg
+        }
+    }
+
+    // the boot rom locks up unless the logo and header checksum are right, the global
+    // checksum is never checked by anything
+    pub fn logo_valid(&self) -> bool { self.logo_valid }
+    pub fn header_checksum(&self) -> u8 { self.header_checksum }
+    pub fn header_checksum_valid(&self) -> bool { self.header_checksum_valid }
+    pub fn global_checksum(&self) -> u16 { self.global_checksum }
+    pub fn global_checksum_valid(&self) -> bool { self.global_checksum_valid }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a 32KB rom with the given title and header bytes, e.g. [(0x143, 0x80)]
+    fn rom(title: &[u8], header: &[(usize, u8)]) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x134..0x134 + title.len()].copy_from_slice(title);
+        for (addr, val) in header {
+            rom[*addr] = *val;
+        }
+        rom
+    }
+
+    #[test]
+    fn checksums() {
+        let mut rom = rom(b"TETRIS", &[(0x147, 0x01)]);
+        rom[0x14d] = Cartridge::compute_header_checksum(&rom);
+        let sum = rom.iter().fold(0u16, |sum, b| sum.wrapping_add(*b as u16));
+        rom[0x14e] = (sum >> 8) as u8;
+        rom[0x14f] = sum as u8;
+        let cart = Cartridge::parse(&rom);
+        assert!(cart.header_checksum_valid());
+        assert!(cart.global_checksum_valid());
+        assert_eq!(cart.global_checksum(), sum);
+
+        // the global checksum covers the whole rom, the header checksum only 0134-014C
+        rom[0x4000] = 1;
+        let cart = Cartridge::parse(&rom);
+        assert!(cart.header_checksum_valid());
+        assert!(!cart.global_checksum_valid());
+
+        rom[0x14c] = 1;
+        assert!(!Cartridge::parse(&rom).header_checksum_valid());
+    }
+
+    #[test]
+    fn title_and_manufacturer() {
+        let cart = Cartridge::parse(&rom(b"POKEMON YELLOW", &[]));
+        assert_eq!(cart.title(), "POKEMON YELLOW");
+        assert_eq!(cart.manufacturer(), None);
+
+        // without the cgb flag all 16 characters are title
+        let cart = Cartridge::parse(&rom(b"ABCDEFGHIJKLMNOP", &[]));
+        assert_eq!(cart.title(), "ABCDEFGHIJKLMNOP");
+
+        let cart = Cartridge::parse(&rom(b"POKEMON SILAAXE", &[(0x143, 0x80)]));
+        assert_eq!(cart.cgb(), CgbSupport::Compatible);
+        assert_eq!(cart.title(), "POKEMON SIL");
+        assert_eq!(cart.manufacturer(), Some("AAXE"));
+
+        // a title that runs into the last 4 characters has no manufacturer code
+        let cart = Cartridge::parse(&rom(b"ZELDA DX tst", &[(0x143, 0xc0)]));
+        assert_eq!(cart.cgb(), CgbSupport::Only);
+        assert_eq!(cart.title(), "ZELDA DX tst");
+        assert_eq!(cart.manufacturer(), None);
+    }
+
+    #[test]
+    fn licensee() {
+        let cart = Cartridge::parse(&rom(b"", &[(0x144, b'0'), (0x145, b'1'), (0x14b, 0x01)]));
+        assert_eq!(cart.licensee(), Licensee::Old(0x01));
+        assert!(cart.licensee().is_nintendo());
+
+        let cart = Cartridge::parse(&rom(b"", &[(0x144, b'0'), (0x145, b'1'), (0x14b, 0x33)]));
+        assert_eq!(cart.licensee(), Licensee::New(*b"01"));
+        assert!(cart.licensee().is_nintendo());
+
+        let cart = Cartridge::parse(&rom(b"", &[(0x144, b'0'), (0x145, b'8'), (0x14b, 0x33)]));
+        assert!(!cart.licensee().is_nintendo());
+    }
+
+    #[test]
+    fn sgb_needs_new_licensee() {
+        assert!(Cartridge::parse(&rom(b"", &[(0x146, 0x03), (0x14b, 0x33)])).sgb());
+        assert!(!Cartridge::parse(&rom(b"", &[(0x146, 0x03), (0x14b, 0x01)])).sgb());
+        assert!(!Cartridge::parse(&rom(b"", &[(0x146, 0x00), (0x14b, 0x33)])).sgb());
+    }
+
+    #[test]
+    fn preferred_model() {
+        assert_eq!(Cartridge::parse(&rom(b"", &[])).preferred_model(), Model::Dmg);
+        assert_eq!(Cartridge::parse(&rom(b"", &[(0x146, 0x03), (0x14b, 0x33)])).preferred_model(), Model::Sgb);
+        assert_eq!(Cartridge::parse(&rom(b"", &[(0x146, 0x03), (0x14b, 0x01)])).preferred_model(), Model::Dmg);
+        assert_eq!(Cartridge::parse(&rom(b"", &[(0x143, 0x80), (0x146, 0x03), (0x14b, 0x33)])).preferred_model(), Model::Cgb);
+        assert_eq!(Cartridge::parse(&rom(b"", &[(0x143, 0xc0)])).preferred_model(), Model::Cgb);
+    }
+
+    #[test]
+    fn rom_size() {
+        assert_eq!(Cartridge::parse(&rom(b"", &[(0x148, 0x00)])).rom_size(), 0x8000);
+        assert_eq!(Cartridge::parse(&rom(b"", &[(0x148, 0x05)])).rom_size(), 0x100000);
+        assert_eq!(Cartridge::parse(&rom(b"", &[(0x148, 0x52)])).rom_size(), 72 * 0x4000);
+        assert_eq!(Cartridge::parse(&rom(b"", &[(0x148, 0x53)])).rom_size(), 80 * 0x4000);
+        assert_eq!(Cartridge::parse(&rom(b"", &[(0x148, 0x54)])).rom_size(), 96 * 0x4000);
+    }
+}
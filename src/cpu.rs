@@ -1,14 +1,75 @@
 use crate::regs::Regs;
-use crate::mmu::MMU;
-use crate::gpu::GPU;
-use std::num::Wrapping;
-
-//use crate::gpu::GPU;
-
-pub struct CPU {
+use crate::bus::Bus;
+use crate::mmu::{LoadError, MMU};
+use crate::interrupts::Interrupt;
+use crate::model::Model;
+use crate::opcodes::{self, FlagEffect, OpInfo, CB_OPCODES, OPCODES};
+use std::fmt;
+
+pub struct CPU<B: Bus = MMU> {
     regs: Regs,
-    mmu: MMU,
+    bus: B,
     halted: bool,
+    halt_bug: bool,     // next opcode fetch fails to increment pc
+    stopped: bool,
+    ime: bool,          // interrupt master enable
+    ei_pending: bool,   // ei only sets ime after the following instruction
+    cycles: u8,         // machine cycles taken so far by the current step
+    fetched: [u8; 3],   // opcode bytes fetched so far by the current step
+    fetched_len: usize,
+    branched: bool,     // whether the current step loaded pc with a jump, call, ret or rst
+    illegal_op_policy: IllegalOpPolicy,
+    locked: bool,       // hung by an illegal opcode, only a reset gets it going again
+    fault: Option<IllegalOpcode>,
+}
+
+// what to do when the cpu fetches one of the 11 opcodes the sm83 doesn't implement
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IllegalOpPolicy {
+    Lock,   // hang like the real hardware, the rest of the system keeps running
+    Stop,   // stop emulation and report the opcode from step
+}
+
+// an illegal opcode was executed under IllegalOpPolicy::Stop
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IllegalOpcode {
+    pub opcode: u8,
+    pub pc: u16,    // address of the opcode
+    pub bank: u16,  // rom bank mapped at pc
+}
+
+impl fmt::Display for IllegalOpcode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "illegal opcode {:#04x} at {:02x}:{:04x}", self.opcode, self.bank, self.pc)
+    }
+}
+
+// what the cpu did during a single step, for debuggers, tracers and profilers
+#[derive(Clone, Debug)]
+pub struct Step {
+    pub pc: u16,                        // pc at the start of the step
+    bytes: [u8; 3],                     // opcode and immediate bytes
+    len: usize,                         // number of bytes, 0 if nothing was executed
+    pub cycles: u8,                     // machine cycles taken
+    pub branch_taken: bool,             // a jump, call, ret or rst loaded pc
+    pub interrupt: Option<Interrupt>,   // interrupt dispatched instead of executing an instruction
+}
+
+impl Step {
+    // opcode and immediate bytes of the executed instruction, empty if nothing was executed
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+
+    // ops.txt entry of the executed instruction
+    pub fn info(&self) -> Option<&'static OpInfo> {
+        opcodes::op_info(self.bytes())
+    }
+
+    // the executed instruction with its operands filled in, e.g. "JR NZ,-5"
+    pub fn mnemonic(&self) -> Option<String> {
+        if self.len == 0 { None } else { Some(opcodes::disassemble(self.bytes())) }
+    }
 }
 
 enum RegIndex {
@@ -34,298 +95,235 @@ enum Condition {
     NC,
 }
 
-impl CPU {
-    pub fn init(rom_file: &str) -> CPU {
-        let mut c = CPU {
+impl CPU<MMU> {
+    // without a boot rom the cpu starts at the cartridge entry point in the state the boot rom
+    // would have left it in
+    pub fn init(rom_file: &str, boot_file: Option<&str>, model: Model) -> Result<CPU<MMU>, LoadError> {
+        let mut cpu = CPU::with_bus(MMU::init(rom_file, boot_file, model)?);
+        cpu.power_up_seq(model);
+        Ok(cpu)
+    }
+}
+
+impl<B: Bus> CPU<B> {
+    // a cpu in its power-on state, see power_up_seq
+    pub fn with_bus(bus: B) -> CPU<B> {
+        CPU {
             regs: Regs::init(),
-            mmu: MMU::init(rom_file, "Roms/DMG_ROM.bin"),
+            bus,
             halted: false,
-        };
+            halt_bug: false,
+            stopped: false,
+            ime: false,
+            ei_pending: false,
+            cycles: 0,
+            fetched: [0; 3],
+            fetched_len: 0,
+            branched: false,
+            illegal_op_policy: IllegalOpPolicy::Lock,
+            locked: false,
+            fault: None,
+        }
+    }
+
+    pub fn bus(&self) -> &B { &self.bus }
+    pub fn bus_mut(&mut self) -> &mut B { &mut self.bus }
+
+    pub fn regs(&self) -> &Regs { &self.regs }
+    pub fn regs_mut(&mut self) -> &mut Regs { &mut self.regs }
+
+    pub fn ime(&self) -> bool { self.ime }
+    pub fn set_ime(&mut self, val: bool) { self.ime = val; }
+
+    pub fn illegal_op_policy(&self) -> IllegalOpPolicy { self.illegal_op_policy }
+    pub fn set_illegal_op_policy(&mut self, policy: IllegalOpPolicy) { self.illegal_op_policy = policy; }
+
+    // whether an illegal opcode hung the cpu under IllegalOpPolicy::Lock
+    pub fn locked(&self) -> bool { self.locked }
+
+    // refer to section 2.7.1. a mapped boot rom runs from 0 with the registers zeroed and
+    // initializes everything itself, otherwise skip straight to its final state
+    pub fn power_up_seq(&mut self, model: Model){
+        if self.bus.boot_rom_mapped() {
+            return
+        }
 
-        c.power_up_seq();
-        c
+        let mut header = [0; 0x50];
+        for (i, byte) in header.iter_mut().enumerate() {
+            *byte = self.bus.read_byte(0x100 + i as u16);
+        }
+        self.regs = Regs::post_boot(model, &header);
+    }
+
+    // every memory access takes one machine cycle, during which the rest of the system is
+    // ticked so that it sees the access at the right time
+    fn read_cycle(&mut self, addr: u16) -> u8 {
+        let data = self.bus.read_byte(addr);
+        self.internal_cycle();
+        data
+    }
+
+    fn write_cycle(&mut self, addr: u16, data: u8) {
+        self.bus.write_byte(addr, data);
+        self.internal_cycle();
     }
 
-    // refer to section 2.7.1
-    pub fn power_up_seq(&mut self){
-        // is is auto-initialized by the instructions from 0-0x100, or do we need to initialize them?
+    // a machine cycle where the cpu is busy without touching memory
+    fn internal_cycle(&mut self) {
+        self.bus.tick(1);
+        self.cycles += 1;
     }
 
     pub fn fetch_ins_byte(&mut self) -> u8 {
-        let op = self.mmu.read_byte(self.regs.pc());
-        self.regs.set_pc(self.regs.pc() + 1);
-        println!("ins: {:#12b}", op);
+        let op = self.read_cycle(self.regs.pc());
+        if self.halt_bug {
+            self.halt_bug = false;
+        } else {
+            self.regs.set_pc(self.regs.pc().wrapping_add(1));
+        }
+        if self.fetched_len < self.fetched.len() {
+            self.fetched[self.fetched_len] = op;
+            self.fetched_len += 1;
+        }
         op
     }
 
     pub fn fetch_ins_word(&mut self) -> u16 {
-        let word = self.mmu.read_word(self.regs.pc());
-        self.regs.set_pc(self.regs.pc() + 2);
-        word
+        let lo = self.fetch_ins_byte();
+        let hi = self.fetch_ins_byte();
+        ((hi as u16) << 8) | (lo as u16)      // little endian
     }
 
-    pub fn cpu_cycle(&mut self) -> u8 {
-        if self.halted { return 1 } 
+    // run the cpu for one instruction, interrupt dispatch or idle cycle while halted. the rest
+    // of the system is ticked along with every machine cycle. once an illegal opcode stopped
+    // emulation every step returns it again without running anything
+    pub fn step(&mut self) -> Result<Step, IllegalOpcode> {
+        if let Some(fault) = self.fault {
+            return Err(fault)
+        }
+
+        let pc = self.regs.pc();
+        self.cycles = 0;
+        self.fetched_len = 0;
+        self.branched = false;
+
+        let interrupt = self.run_step();
+        if let Some(fault) = self.fault {
+            return Err(fault)
+        }
+
+        Ok(Step {
+            pc,
+            bytes: self.fetched,
+            len: self.fetched_len,
+            cycles: self.cycles,
+            branch_taken: self.branched,
+            interrupt,
+        })
+    }
+
+    // returns the interrupt dispatched, if any
+    fn run_step(&mut self) -> Option<Interrupt> {
+        // a locked cpu never fetches again and ignores interrupts, time still passes though
+        if self.locked {
+            self.internal_cycle();
+            return None
+        }
+
+        // stop mode is only left by pressing a button
+        if self.stopped {
+            if self.buttons_held() { self.stopped = false; }
+            self.internal_cycle();
+            return None
+        }
+
+        // halt mode is left once any enabled interrupt is requested, even if ime is reset
+        if self.halted {
+            if self.bus.pending_interrupts() != 0 { self.halted = false; }
+            self.internal_cycle();
+            return None
+        }
+
+        if self.ime && self.bus.pending_interrupts() != 0 {
+            return self.handle_interrupts()
+        }
+
+        // ime is set once the instruction after ei has executed, unless it was a di
+        let enable_ime = self.ei_pending;
+        self.execute();
+        if enable_ime && self.ei_pending {
+            self.ime = true;
+            self.ei_pending = false;
+        }
+
+        None
+    }
+
+    // whether any button is pressed on the currently selected joypad lines
+    fn buttons_held(&mut self) -> bool {
+        self.bus.read_byte(0xff00) & 0x0f != 0x0f
+    }
+
+    // service the highest priority pending interrupt, returns the one serviced. this is None if
+    // the interrupt was cancelled while pushing pc
+    fn handle_interrupts(&mut self) -> Option<Interrupt> {
+        self.ime = false;
+        self.internal_cycle();
+        self.internal_cycle();
+
+        // after EI then HALT with an interrupt pending, the halt bug is still waiting to repeat
+        // the fetch. the interrupt comes first and returns to the halt, which runs again
+        let mut pc = self.regs.pc();
+        if self.halt_bug {
+            self.halt_bug = false;
+            pc = pc.wrapping_sub(1);
+        }
+
+        // the interrupt to service is only picked after the high byte of pc is pushed. if that
+        // push overwrote IE and nothing is left pending, the cpu jumps to 0 instead
+        self.regs.set_sp(self.regs.sp().wrapping_sub(1));
+        self.write_cycle(self.regs.sp(), (pc >> 8) as u8);
+        let pending = self.bus.pending_interrupts();
+        self.regs.set_sp(self.regs.sp().wrapping_sub(1));
+        self.write_cycle(self.regs.sp(), (pc & 0x00ff) as u8);
+
+        let intr = Interrupt::highest(pending);
+        match intr {
+            Some(intr) => { self.bus.ack_interrupt(intr); self.regs.set_pc(intr.vector()); },
+            None => self.regs.set_pc(0),
+        }
+        self.internal_cycle();
+        self.branched = true;
+
+        intr
+    }
+
+    // fetch, decode and execute a single instruction. the cycles and length ops.txt lists for
+    // it are checked against the cycles it actually took and the bytes it fetched, except for
+    // the length of STOP, whose padding byte is skipped over without being read
+    fn execute(&mut self) {
         let op = self.fetch_ins_byte();
-        
-        match op {
-            0x00 => { 1 },
-            0x01 => { let data = self.fetch_ins_word(); self.ld_word(data, Some(RegIndex::BC), 0); 3 },
-            0x02 => { self.ld_byte(self.regs.a(), None, self.regs.bc()); 2 },
-            0x03 => { self.inc(Some(RegIndex::BC), 0); 2 },
-            0x04 => { self.inc(Some(RegIndex::B), 0); 1 },
-            0x05 => { self.dec(Some(RegIndex::B), 0); 1 },
-            0x06 => { let data = self.fetch_ins_byte(); self.ld_byte(data, Some(RegIndex::B), 0); 2 },
-            0x07 => { self.rlca(); 1 },
-            0x08 => { let addr = self.fetch_ins_word(); self.ld_word(self.regs.sp(), None, addr); 5 },
-            0x09 => { self.add_word(self.regs.bc(), RegIndex::HL); 2 },
-            0x0a => { let data = self.mmu.read_byte(self.regs.bc()); self.ld_byte(data, Some(RegIndex::A), 0); 2 },
-            0x0b => { self.dec(Some(RegIndex::BC), 0); 2 },
-            0x0c => { self.inc(Some(RegIndex::C), 0); 1 },
-            0x0d => { self.dec(Some(RegIndex::C), 0); 1 },
-            0x0e => { let data = self.fetch_ins_byte(); self.ld_byte(data, Some(RegIndex::C), 0); 2 },
-            0x0f => { self.rrca(); 1 },
-            0x10 => { self.stop(); 1 },
-            0x11 => { let data = self.fetch_ins_word(); self.ld_word(data, Some(RegIndex::DE), 0); 3 },
-            0x12 => { self.ld_byte(self.regs.a(), None, self.regs.de()); 2 },
-            0x13 => { self.inc(Some(RegIndex::DE), 0); 2 },
-            0x14 => { self.inc(Some(RegIndex::D), 0); 1 },
-            0x15 => { self.dec(Some(RegIndex::D), 0); 1 },
-            0x16 => { let data = self.fetch_ins_byte(); self.ld_byte(data, Some(RegIndex::D), 0); 2 },
-            0x17 => { self.rla(); 1 },
-            0x18 => { self.jr(None); 3 },
-            0x19 => { self.add_word(self.regs.de(), RegIndex::HL); 2 },
-            0x1a => { let data = self.mmu.read_byte(self.regs.de()); self.ld_byte(data, Some(RegIndex::A), 0); 2 },
-            0x1b => { self.dec(Some(RegIndex::DE), 0); 2 },
-            0x1c => { self.inc(Some(RegIndex::E), 0); 1 },
-            0x1d => { self.dec(Some(RegIndex::E), 0); 1 },
-            0x1e => { let data = self.fetch_ins_byte(); self.ld_byte(data, Some(RegIndex::E), 0); 2 },
-            0x1f => { self.rra(); 1 },
-            0x20 => { self.jr(Some(Condition::NZ)) as u8 + 2 },
-            0x21 => { let data = self.fetch_ins_word(); self.ld_word(data, Some(RegIndex::HL), 0); 3 },
-            0x22 => { self.ld_byte(self.regs.a(), None, self.regs.hl()); self.regs.set_hl(self.regs.hl().wrapping_add(1)); 2 },
-            0x23 => { self.inc(Some(RegIndex::HL), 0); 2 },
-            0x24 => { self.inc(Some(RegIndex::H), 0); 1 },
-            0x25 => { self.dec(Some(RegIndex::H), 0); 1 },
-            0x26 => { let data = self.fetch_ins_byte(); self.ld_byte(data, Some(RegIndex::H), 0); 2 },
-            0x27 => { self.daa(); 1 },
-            0x28 => { self.jr(Some(Condition::Z)) as u8 + 2 },
-            0x29 => { self.add_word(self.regs.hl(), RegIndex::HL); 2 },
-            0x2a => { let data = self.mmu.read_byte(self.regs.hl()); self.ld_byte(data, Some(RegIndex::A), 0); self.regs.set_hl(self.regs.hl().wrapping_add(1)); 2 },
-            0x2b => { self.dec(Some(RegIndex::HL), 0); 2 },
-            0x2c => { self.inc(Some(RegIndex::L), 0); 1 },
-            0x2d => { self.dec(Some(RegIndex::L), 0); 1 },
-            0x2e => { let data = self.fetch_ins_byte(); self.ld_byte(data, Some(RegIndex::L), 0); 2 },
-            0x2f => { self.cpl(); 1 },
-            0x30 => { self.jr(Some(Condition::NC)) as u8 + 2 },
-            0x31 => { let data = self.fetch_ins_word(); self.ld_word(data, Some(RegIndex::SP), 0); 3 },
-            0x32 => { self.ld_byte(self.regs.a(), None, self.regs.hl()); self.regs.set_hl(self.regs.hl().wrapping_sub(1)); 2 },
-            0x33 => { self.inc(Some(RegIndex::SP), 0); 2 },
-            0x34 => { self.inc(None, self.regs.hl()); 3 },
-            0x35 => { self.dec(None, self.regs.hl()); 3 },
-            0x36 => { let data = self.fetch_ins_byte(); self.ld_byte(data, None, self.regs.hl()); 3 },
-            0x37 => { self.scf(); 1 },
-            0x38 => { self.jr(Some(Condition::C)) as u8 + 2 },
-            0x39 => { self.add_word(self.regs.sp(), RegIndex::HL); 2 },
-            0x3a => { let data = self.mmu.read_byte(self.regs.hl()); self.ld_byte(data, Some(RegIndex::A), 0); self.regs.set_hl(self.regs.hl().wrapping_sub(1)); 2 },
-            0x3b => { self.dec(Some(RegIndex::SP), 0); 2 },
-            0x3c => { self.inc(Some(RegIndex::A), 0); 1 },
-            0x3d => { self.dec(Some(RegIndex::A), 0); 1 },
-            0x3e => { let data = self.fetch_ins_byte(); self.ld_byte(data, Some(RegIndex::A), 0); 2 },
-            0x3f => { self.ccf(); 1 },
-            0x40 => { self.ld_byte(self.regs.b(), Some(RegIndex::B), 0); 1 },
-            0x41 => { self.ld_byte(self.regs.c(), Some(RegIndex::B), 0); 1 },
-            0x42 => { self.ld_byte(self.regs.d(), Some(RegIndex::B), 0); 1 },
-            0x43 => { self.ld_byte(self.regs.e(), Some(RegIndex::B), 0); 1 },
-            0x44 => { self.ld_byte(self.regs.h(), Some(RegIndex::B), 0); 1 },
-            0x45 => { self.ld_byte(self.regs.l(), Some(RegIndex::B), 0); 1 },
-            0x46 => { let data = self.mmu.read_byte(self.regs.hl()); self.ld_byte(data, Some(RegIndex::B), 0); 2 },
-            0x47 => { self.ld_byte(self.regs.a(), Some(RegIndex::B), 0); 1 },
-            0x48 => { self.ld_byte(self.regs.b(), Some(RegIndex::C), 0); 1 },
-            0x49 => { self.ld_byte(self.regs.c(), Some(RegIndex::C), 0); 1 },
-            0x4a => { self.ld_byte(self.regs.d(), Some(RegIndex::C), 0); 1 },
-            0x4b => { self.ld_byte(self.regs.e(), Some(RegIndex::C), 0); 1 },
-            0x4c => { self.ld_byte(self.regs.h(), Some(RegIndex::C), 0); 1 },
-            0x4d => { self.ld_byte(self.regs.l(), Some(RegIndex::C), 0); 1 },
-            0x4e => { let data = self.mmu.read_byte(self.regs.hl()); self.ld_byte(data, Some(RegIndex::C), 0); 2 },
-            0x4f => { self.ld_byte(self.regs.a(), Some(RegIndex::C), 0); 1 },
-            0x50 => { self.ld_byte(self.regs.b(), Some(RegIndex::D), 0); 1 },
-            0x51 => { self.ld_byte(self.regs.c(), Some(RegIndex::D), 0); 1 },
-            0x52 => { self.ld_byte(self.regs.d(), Some(RegIndex::D), 0); 1 },
-            0x53 => { self.ld_byte(self.regs.e(), Some(RegIndex::D), 0); 1 },
-            0x54 => { self.ld_byte(self.regs.h(), Some(RegIndex::D), 0); 1 },
-            0x55 => { self.ld_byte(self.regs.l(), Some(RegIndex::D), 0); 1 },
-            0x56 => { let data = self.mmu.read_byte(self.regs.hl()); self.ld_byte(data, Some(RegIndex::D), 0); 2 },
-            0x57 => { self.ld_byte(self.regs.a(), Some(RegIndex::D), 0); 1 },
-            0x58 => { self.ld_byte(self.regs.b(), Some(RegIndex::E), 0); 1 },
-            0x59 => { self.ld_byte(self.regs.c(), Some(RegIndex::E), 0); 1 },
-            0x5a => { self.ld_byte(self.regs.d(), Some(RegIndex::E), 0); 1 },
-            0x5b => { self.ld_byte(self.regs.e(), Some(RegIndex::E), 0); 1 },
-            0x5c => { self.ld_byte(self.regs.h(), Some(RegIndex::E), 0); 1 },
-            0x5d => { self.ld_byte(self.regs.l(), Some(RegIndex::E), 0); 1 },
-            0x5e => { let data = self.mmu.read_byte(self.regs.hl()); self.ld_byte(data, Some(RegIndex::E), 0); 2 },
-            0x5f => { self.ld_byte(self.regs.a(), Some(RegIndex::E), 0); 1 },
-            0x60 => { self.ld_byte(self.regs.b(), Some(RegIndex::H), 0); 1 },
-            0x61 => { self.ld_byte(self.regs.c(), Some(RegIndex::H), 0); 1 },
-            0x62 => { self.ld_byte(self.regs.d(), Some(RegIndex::H), 0); 1 },
-            0x63 => { self.ld_byte(self.regs.e(), Some(RegIndex::H), 0); 1 },
-            0x64 => { self.ld_byte(self.regs.h(), Some(RegIndex::H), 0); 1 },
-            0x65 => { self.ld_byte(self.regs.l(), Some(RegIndex::H), 0); 1 },
-            0x66 => { let data = self.mmu.read_byte(self.regs.hl()); self.ld_byte(data, Some(RegIndex::H), 0); 2 },
-            0x67 => { self.ld_byte(self.regs.a(), Some(RegIndex::H), 0); 1 },
-            0x68 => { self.ld_byte(self.regs.b(), Some(RegIndex::L), 0); 1 },
-            0x69 => { self.ld_byte(self.regs.c(), Some(RegIndex::L), 0); 1 },
-            0x6a => { self.ld_byte(self.regs.d(), Some(RegIndex::L), 0); 1 },
-            0x6b => { self.ld_byte(self.regs.e(), Some(RegIndex::L), 0); 1 },
-            0x6c => { self.ld_byte(self.regs.h(), Some(RegIndex::L), 0); 1 },
-            0x6d => { self.ld_byte(self.regs.l(), Some(RegIndex::L), 0); 1 },
-            0x6e => { let data = self.mmu.read_byte(self.regs.hl()); self.ld_byte(data, Some(RegIndex::L), 0); 2 },
-            0x6f => { self.ld_byte(self.regs.a(), Some(RegIndex::L), 0); 1 },
-            0x70 => { self.ld_byte(self.regs.b(), None, self.regs.hl()); 2 },
-            0x71 => { self.ld_byte(self.regs.c(), None, self.regs.hl()); 2 },
-            0x72 => { self.ld_byte(self.regs.d(), None, self.regs.hl()); 2 },
-            0x73 => { self.ld_byte(self.regs.e(), None, self.regs.hl()); 2 },
-            0x74 => { self.ld_byte(self.regs.h(), None, self.regs.hl()); 2 },
-            0x75 => { self.ld_byte(self.regs.l(), None, self.regs.hl()); 2 },
-            0x76 => { self.halt(); 1 },
-            0x77 => { self.ld_byte(self.regs.a(), None, self.regs.hl()); 2 },
-            0x78 => { self.ld_byte(self.regs.b(), Some(RegIndex::A), 0); 1 },
-            0x79 => { self.ld_byte(self.regs.c(), Some(RegIndex::A), 0); 1 },
-            0x7a => { self.ld_byte(self.regs.d(), Some(RegIndex::A), 0); 1 },
-            0x7b => { self.ld_byte(self.regs.e(), Some(RegIndex::A), 0); 1 },
-            0x7c => { self.ld_byte(self.regs.h(), Some(RegIndex::A), 0); 1 },
-            0x7d => { self.ld_byte(self.regs.l(), Some(RegIndex::A), 0); 1 },
-            0x7e => { let data = self.mmu.read_byte(self.regs.hl()); self.ld_byte(data, Some(RegIndex::A), 0); 2 },
-            0x7f => { self.ld_byte(self.regs.a(), Some(RegIndex::A), 0); 1 },
-            0x80 => { self.add_byte(self.regs.b(), false); 1 },
-            0x81 => { self.add_byte(self.regs.c(), false); 1 },
-            0x82 => { self.add_byte(self.regs.d(), false); 1 },
-            0x83 => { self.add_byte(self.regs.e(), false); 1 },
-            0x84 => { self.add_byte(self.regs.h(), false); 1 },
-            0x85 => { self.add_byte(self.regs.l(), false); 1 },
-            0x86 => { let data = self.mmu.read_byte(self.regs.hl()); self.add_byte(data, false); 2 },
-            0x87 => { self.add_byte(self.regs.a(), false); 1 },
-            0x88 => { self.add_byte(self.regs.b(), true); 1 },
-            0x89 => { self.add_byte(self.regs.c(), true); 1 },
-            0x8a => { self.add_byte(self.regs.d(), true); 1 },
-            0x8b => { self.add_byte(self.regs.e(), true); 1 },
-            0x8c => { self.add_byte(self.regs.h(), true); 1 },
-            0x8d => { self.add_byte(self.regs.l(), true); 1 },
-            0x8e => { let data = self.mmu.read_byte(self.regs.hl()); self.add_byte(data, true); 2 },
-            0x8f => { self.add_byte(self.regs.a(), true); 1 },
-            0x90 => { self.sub_byte(self.regs.b(), false); 1 },
-            0x91 => { self.sub_byte(self.regs.c(), false); 1 },
-            0x92 => { self.sub_byte(self.regs.d(), false); 1 },
-            0x93 => { self.sub_byte(self.regs.e(), false); 1 },
-            0x94 => { self.sub_byte(self.regs.h(), false); 1 },
-            0x95 => { self.sub_byte(self.regs.l(), false); 1 },
-            0x96 => { let data = self.mmu.read_byte(self.regs.hl()); self.sub_byte(data, false); 2 },
-            0x97 => { self.sub_byte(self.regs.a(), false); 1 },
-            0x98 => { self.sub_byte(self.regs.b(), true); 1 },
-            0x99 => { self.sub_byte(self.regs.c(), true); 1 },
-            0x9a => { self.sub_byte(self.regs.d(), true); 1 },
-            0x9b => { self.sub_byte(self.regs.e(), true); 1 },
-            0x9c => { self.sub_byte(self.regs.h(), true); 1 },
-            0x9d => { self.sub_byte(self.regs.l(), true); 1 },
-            0x9e => { let data = self.mmu.read_byte(self.regs.hl()); self.sub_byte(data, true); 2 },
-            0x9f => { self.sub_byte(self.regs.a(), true); 1 },
-            0xa0 => { self.and(self.regs.b()); 1 },
-            0xa1 => { self.and(self.regs.c()); 1 },
-            0xa2 => { self.and(self.regs.d()); 1 },
-            0xa3 => { self.and(self.regs.e()); 1 },
-            0xa4 => { self.and(self.regs.h()); 1 },
-            0xa5 => { self.and(self.regs.l()); 1 },
-            0xa6 => { let data = self.mmu.read_byte(self.regs.hl()); self.and(data); 2 },
-            0xa7 => { self.and(self.regs.a()); 1 },
-            0xa8 => { self.xor(self.regs.b()); 1 },
-            0xa9 => { self.xor(self.regs.c()); 1 },
-            0xaa => { self.xor(self.regs.d()); 1 },
-            0xab => { self.xor(self.regs.e()); 1 },
-            0xac => { self.xor(self.regs.h()); 1 },
-            0xad => { self.xor(self.regs.l()); 1 },
-            0xae => { let data = self.mmu.read_byte(self.regs.hl()); self.xor(data); 2 },
-            0xaf => { self.xor(self.regs.a()); 1 },
-            0xb0 => { self.or(self.regs.b()); 1 },
-            0xb1 => { self.or(self.regs.c()); 1 },
-            0xb2 => { self.or(self.regs.d()); 1 },
-            0xb3 => { self.or(self.regs.e()); 1 },
-            0xb4 => { self.or(self.regs.h()); 1 },
-            0xb5 => { self.or(self.regs.l()); 1 },
-            0xb6 => { let data = self.mmu.read_byte(self.regs.hl()); self.or(data); 2 },
-            0xb7 => { self.or(self.regs.a()); 1 },
-            0xb8 => { self.cp(self.regs.b()); 1 },
-            0xb9 => { self.cp(self.regs.c()); 1 },
-            0xba => { self.cp(self.regs.d()); 1 },
-            0xbb => { self.cp(self.regs.e()); 1 },
-            0xbc => { self.cp(self.regs.h()); 1 },
-            0xbd => { self.cp(self.regs.l()); 1 },
-            0xbe => { let data = self.mmu.read_byte(self.regs.hl()); self.cp(data); 2 },
-            0xbf => { self.cp(self.regs.a()); 1 },
-            0xc0 => { self.ret(Some(Condition::NZ)) as u8 * 3 + 2 },
-            0xc1 => { self.pop(RegIndex::BC); 3 },
-            0xc2 => { let addr = self.fetch_ins_word(); self.jump(addr, Some(Condition::NZ)) as u8 + 3 },
-            0xc3 => { let addr = self.fetch_ins_word(); self.jump(addr, None); 4 },
-            0xc4 => { self.call(Some(Condition::NZ)) as u8 * 3 + 3 },
-            0xc5 => { self.push(self.regs.bc()); 4 },
-            0xc6 => { let data = self.fetch_ins_byte(); self.add_byte(data, false); 2 },
-            0xc7 => { self.rst(0x00 as u16); 4 },
-            0xc8 => { self.ret(Some(Condition::Z)) as u8 * 3 + 2 },
-            0xc9 => { self.ret(None); 4 },
-            0xca => { let addr = self.fetch_ins_word(); self.jump(addr, Some(Condition::Z)) as u8 + 3 },
-            0xcb => { self.decode_cb() + 1 },
-            0xcc => { self.call(Some(Condition::Z)) as u8 * 3 + 3 },
-            0xcd => { self.call(None); 6 },
-            0xce => { let data = self.fetch_ins_byte(); self.add_byte(data, true); 2 },
-            0xcf => { self.rst(0x08 as u16); 4 },
-            0xd0 => { self.ret(Some(Condition::NC)) as u8 * 3 + 2 },
-            0xd1 => { self.pop(RegIndex::DE); 3 },
-            0xd2 => { let addr = self.fetch_ins_word(); self.jump(addr, Some(Condition::NC)) as u8 + 3 },
-            0xd3 => { self.undefined_op(op); 1 },
-            0xd4 => { self.call(Some(Condition::NC)) as u8 * 3 + 3 },
-            0xd5 => { self.push(self.regs.de()); 4 },
-            0xd6 => { let data = self.fetch_ins_byte(); self.sub_byte(data, false); 2 },
-            0xd7 => { self.rst(0x10 as u16); 4 },
-            0xd8 => { self.ret(Some(Condition::C)) as u8 * 5 + 2 },
-            0xd9 => { self.ret(None); 4 },
-            0xda => { let addr = self.fetch_ins_word(); self.jump(addr, Some(Condition::C)) as u8 + 3 },
-            0xdb => { self.undefined_op(op); 1 },
-            0xdc => { self.call(Some(Condition::C)) as u8 * 3 + 3 },
-            0xdd => { self.undefined_op(op); 1 },
-            0xde => { let data = self.fetch_ins_byte(); self.sub_byte(data, true); 2 },
-            0xdf => { self.rst(0x18 as u16); 4 },
-            0xe0 => { let addr = self.fetch_ins_byte() as u16 + 0xff00; self.ld_byte(self.regs.a(), None, addr); 3 },
-            0xe1 => { self.pop(RegIndex::HL); 3 },
-            0xe2 => { self.ld_byte(self.regs.a(), None, self.regs.c() as u16 + 0xff00); 2 },
-            0xe3 => { self.undefined_op(op); 1 },
-            0xe4 => { self.undefined_op(op); 1 },
-            0xe5 => { self.push(self.regs.hl()); 4 },
-            0xe6 => { let data = self.fetch_ins_byte(); self.and(data); 2 },
-            0xe7 => { self.rst(0x20 as u16); 4 },
-            0xe8 => { let data = self.fetch_ins_byte() as i8 as i16 as u16; self.add_word(data, RegIndex::SP); 4 },
-            0xe9 => { self.jump(self.regs.hl(), None); 1 },
-            0xea => { let addr = self.fetch_ins_word(); self.ld_byte(self.regs.a(), None, addr); 4 },
-            0xeb => { self.undefined_op(op); 1 },
-            0xec => { self.undefined_op(op); 1 },
-            0xed => { self.undefined_op(op); 1 },
-            0xee => { let data = self.fetch_ins_byte(); self.xor(data); 2 },
-            0xef => { self.rst(0x28 as u16); 4 },
-            0xf0 => { let addr = self.fetch_ins_byte() as u16 + 0xff00; let data = self.mmu.read_byte(addr); self.ld_byte(data, Some(RegIndex::A), 0); 3 },
-            0xf1 => { self.pop(RegIndex::AF); 3 },
-            0xf2 => { let addr = self.regs.c() as u16 + 0xff00; let data = self.mmu.read_byte(addr); self.ld_byte(data, Some(RegIndex::A), 0); 2 },
-            0xf3 => { self.di(); 1 },
-            0xf4 => { self.undefined_op(op); 1 },
-            0xf5 => { self.push(self.regs.af()); 4 },
-            0xf6 => { let data = self.fetch_ins_byte(); self.or(data); 2 },
-            0xf7 => { self.rst(0x30 as u16); 4 },
-            0xf8 => { let data = self.fetch_ins_byte() as i8 as i16 as u16; let old_sp = self.regs.sp(); self.add_word(data, RegIndex::SP); self.ld_word(self.regs.sp(), Some(RegIndex::HL), 0); self.regs.set_sp(old_sp); self.regs.set_zflag(false); 3 },
-            0xf9 => { self.ld_word(self.regs.hl(), Some(RegIndex::SP), 0); 2 },
-            0xfa => { let addr = self.fetch_ins_word(); let data = self.mmu.read_byte(addr); self.ld_byte(data, Some(RegIndex::A), 0); 4 },
-            0xfb => { self.ei(); 1 },
-            0xfc => { self.undefined_op(op); 1 },
-            0xfd => { self.undefined_op(op); 1 },
-            0xfe => { let data = self.fetch_ins_byte(); self.cp(data); 2 },
-            0xff => { self.rst(0x38 as u16); 4 },
-            _ => { self.undefined_op(op); 1 },
+        let expected_cycles: u8 = include!(concat!(env!("OUT_DIR"), "/dispatch.rs"));
+        debug_assert_eq!(self.cycles, expected_cycles, "timing of {:#04x} doesn't match ops.txt", op);
+        if let Some(info) = opcodes::op_info(&self.fetched[..self.fetched_len]) {
+            debug_assert!(op == 0x10 || self.fetched_len == info.length as usize,
+                "length of {:#04x} doesn't match ops.txt", op);
+        }
+
+        if let Some(info) = &OPCODES[op as usize] {
+            self.apply_flag_effects(info);
+        }
+    }
+
+    // force the flags that ops.txt lists as always reset or set by an instruction
+    fn apply_flag_effects(&mut self, info: &OpInfo) {
+        let setters: [fn(&mut Regs, bool); 4] = [Regs::set_zflag, Regs::set_sflag, Regs::set_hflag, Regs::set_cflag];
+        for (effect, set_flag) in info.flags.iter().zip(setters.iter()) {
+            match effect {
+                FlagEffect::Reset => set_flag(&mut self.regs, false),
+                FlagEffect::Set => set_flag(&mut self.regs, true),
+                _ => (),
+            }
         }
     }
 
@@ -346,7 +344,7 @@ impl CPU {
                 _ => (),
             };
         } else {
-            self.mmu.write_byte(dest, src);
+            self.write_cycle(dest, src);
         }
     }
 
@@ -361,10 +359,44 @@ impl CPU {
                 _ => (),
             };
         } else {
-            self.mmu.write_word(dest, src);
+            self.write_cycle(dest, (src & 0x00ff) as u8);      // little endian
+            self.write_cycle(dest.wrapping_add(1), (src >> 8) as u8);
         }
     }
 
+    // SP plus a signed 8 bit offset, half carry and carry come from adding the low byte
+    fn sp_offset(&mut self, offset: u8) -> u16 {
+        let sp = self.regs.sp();
+
+        self.regs.set_zflag(false);
+        self.regs.set_sflag(false);
+        self.regs.set_hflag((sp & 0x0f) + (offset as u16 & 0x0f) > 0x0f);
+        self.regs.set_cflag((sp & 0xff) + (offset as u16) > 0xff);
+
+        sp.wrapping_add(offset as i8 as i16 as u16)
+    }
+
+    // add a signed 8 bit offset to SP
+    fn add_sp(&mut self, offset: u8) {
+        let res = self.sp_offset(offset);
+        self.internal_cycle();
+        self.internal_cycle();
+        self.regs.set_sp(res);
+    }
+
+    // load SP plus a signed 8 bit offset into HL
+    fn ld_hl_sp_offset(&mut self, offset: u8) {
+        let res = self.sp_offset(offset);
+        self.internal_cycle();
+        self.regs.set_hl(res);
+    }
+
+    // load HL into SP, which takes a cycle to go over the 16 bit path
+    fn ld_sp_hl(&mut self) {
+        self.internal_cycle();
+        self.regs.set_sp(self.regs.hl());
+    }
+
     // add given byte to accumulator (reg A)
     fn add_byte(&mut self, src: u8, carry: bool) {
         let c = if carry { self.regs.cflag() as u8 } else { 0 };
@@ -378,14 +410,14 @@ impl CPU {
         self.regs.set_cflag((src as u16) + (old_a as u16) + (c as u16) > 0xff);
     }
 
-    // add given reg or 8-bit immediate value to given reg
+    // add given 16 bit reg to given reg, only HL is a valid destination
     fn add_word(&mut self, src: u16, reg: RegIndex) {
         let old_val: Option<u16>;
         match reg {
             RegIndex::HL => { old_val = Some(self.regs.hl()); self.regs.set_hl(old_val.unwrap().wrapping_add(src)); },
-            RegIndex::SP => { old_val = Some(self.regs.sp()); self.regs.set_sp(old_val.unwrap().wrapping_add(src)); self.regs.set_zflag(false); },
             _ => old_val = None,
         };
+        self.internal_cycle();
      
         // set flags
         self.regs.set_sflag(false);
@@ -399,12 +431,12 @@ impl CPU {
     fn sub_byte(&mut self, src: u8, carry: bool) {
         let c = if carry { self.regs.cflag() as u8 } else { 0 };
         let old_a = self.regs.a();
-        self.regs.set_a(self.regs.a().wrapping_add(src.wrapping_sub(c)));
+        self.regs.set_a(old_a.wrapping_sub(src).wrapping_sub(c));
 
         // set flags
         self.regs.set_sflag(true);
         self.regs.set_zflag(self.regs.a() == 0);
-        self.regs.set_hflag((src & 0x0f) + (old_a & 0x0f) + c > 0x0f);
+        self.regs.set_hflag((old_a & 0x0f) < (src & 0x0f) + c);
         self.regs.set_cflag((old_a as u16) < (src as u16) + (c as u16));
     }
 
@@ -420,15 +452,15 @@ impl CPU {
                 RegIndex::E => { old_val = Some(self.regs.e()); self.regs.set_e(old_val.unwrap().wrapping_add(1)); },
                 RegIndex::H => { old_val = Some(self.regs.h()); self.regs.set_h(old_val.unwrap().wrapping_add(1)); },
                 RegIndex::L => { old_val = Some(self.regs.l()); self.regs.set_l(old_val.unwrap().wrapping_add(1)); },
-                RegIndex::BC => self.regs.set_bc(self.regs.bc().wrapping_add(1)),
-                RegIndex::DE => self.regs.set_de(self.regs.de().wrapping_add(1)),
-                RegIndex::HL => self.regs.set_hl(self.regs.hl().wrapping_add(1)),
-                RegIndex::SP => self.regs.set_sp(self.regs.sp().wrapping_add(1)),
+                RegIndex::BC => { self.regs.set_bc(self.regs.bc().wrapping_add(1)); self.internal_cycle(); },
+                RegIndex::DE => { self.regs.set_de(self.regs.de().wrapping_add(1)); self.internal_cycle(); },
+                RegIndex::HL => { self.regs.set_hl(self.regs.hl().wrapping_add(1)); self.internal_cycle(); },
+                RegIndex::SP => { self.regs.set_sp(self.regs.sp().wrapping_add(1)); self.internal_cycle(); },
                 _ => (),
             };
         } else {
-            old_val = Some(self.mmu.read_byte(dest));
-            self.mmu.write_byte(dest, old_val.unwrap().wrapping_add(1));
+            old_val = Some(self.read_cycle(dest));
+            self.write_cycle(dest, old_val.unwrap().wrapping_add(1));
         }
 
         if let Some(v) = old_val {
@@ -450,15 +482,15 @@ impl CPU {
                 RegIndex::E => { old_val = Some(self.regs.e()); self.regs.set_e(old_val.unwrap().wrapping_sub(1)); },
                 RegIndex::H => { old_val = Some(self.regs.h()); self.regs.set_h(old_val.unwrap().wrapping_sub(1)); },
                 RegIndex::L => { old_val = Some(self.regs.l()); self.regs.set_l(old_val.unwrap().wrapping_sub(1)); },
-                RegIndex::BC => self.regs.set_bc(self.regs.bc().wrapping_sub(1)),
-                RegIndex::DE => self.regs.set_de(self.regs.bc().wrapping_sub(1)),
-                RegIndex::HL => self.regs.set_hl(self.regs.bc().wrapping_sub(1)),
-                RegIndex::SP => self.regs.set_sp(self.regs.bc().wrapping_sub(1)),
+                RegIndex::BC => { self.regs.set_bc(self.regs.bc().wrapping_sub(1)); self.internal_cycle(); },
+                RegIndex::DE => { self.regs.set_de(self.regs.de().wrapping_sub(1)); self.internal_cycle(); },
+                RegIndex::HL => { self.regs.set_hl(self.regs.hl().wrapping_sub(1)); self.internal_cycle(); },
+                RegIndex::SP => { self.regs.set_sp(self.regs.sp().wrapping_sub(1)); self.internal_cycle(); },
                 _ => (),
             };
         } else {
-            old_val = Some(self.mmu.read_byte(dest));
-            self.mmu.write_byte(dest, old_val.unwrap().wrapping_sub(1));
+            old_val = Some(self.read_cycle(dest));
+            self.write_cycle(dest, old_val.unwrap().wrapping_sub(1));
         }
 
         if let Some(v) = old_val {
@@ -511,24 +543,45 @@ impl CPU {
         self.jump(jump_loc, cond)
     }
 
+    // taking a jump costs a cycle to load pc
     fn jump(&mut self, val: u16, cond: Option<Condition>) -> bool {
-        match cond {
-            Some(Condition::Z) => { if self.regs.zflag() { self.regs.set_pc(val); return true } false },
-            Some(Condition::NZ) => { if !self.regs.zflag() { self.regs.set_pc(val); return true } false },
-            Some(Condition::C) => { if self.regs.cflag() { self.regs.set_pc(val); return true } false },
-            Some(Condition::NC) => { if !self.regs.cflag() { self.regs.set_pc(val); return true } false },
-            None => { self.regs.set_pc(val); true },
+        let taken = match cond {
+            Some(Condition::Z) => self.regs.zflag(),
+            Some(Condition::NZ) => !self.regs.zflag(),
+            Some(Condition::C) => self.regs.cflag(),
+            Some(Condition::NC) => !self.regs.cflag(),
+            None => true,
+        };
+
+        if taken {
+            self.regs.set_pc(val);
+            self.internal_cycle();
+            self.branched = true;
         }
+
+        taken
     }
 
+    // jp (hl) loads pc directly from HL without the extra cycle of other jumps
+    fn jp_hl(&mut self) {
+        self.regs.set_pc(self.regs.hl());
+        self.branched = true;
+    }
+
+    // the high byte is written first, to the higher address, after a cycle to decrement SP
     fn push(&mut self, data: u16) {
-        self.regs.set_sp(self.regs.sp() - 2);
-        self.mmu.write_word(self.regs.sp(), data);
+        self.internal_cycle();
+        self.regs.set_sp(self.regs.sp().wrapping_sub(1));
+        self.write_cycle(self.regs.sp(), (data >> 8) as u8);
+        self.regs.set_sp(self.regs.sp().wrapping_sub(1));
+        self.write_cycle(self.regs.sp(), (data & 0x00ff) as u8);
     }
 
     fn pop(&mut self, reg: RegIndex) {
-        let data = self.mmu.read_word(self.regs.sp());
-        self.regs.set_sp(self.regs.sp() + 2);
+        let lo = self.read_cycle(self.regs.sp());
+        let hi = self.read_cycle(self.regs.sp().wrapping_add(1));
+        let data = ((hi as u16) << 8) | (lo as u16);
+        self.regs.set_sp(self.regs.sp().wrapping_add(2));
         
         match reg {
             RegIndex::AF => self.regs.set_af(data),
@@ -554,11 +607,14 @@ impl CPU {
         if didCall {
             self.push(self.regs.pc());
             self.regs.set_pc(addr);
+            self.branched = true;
         }
 
         didCall
     }
 
+    // conditional returns take a cycle to check the condition, and a taken return takes another
+    // to load pc
     fn ret(&mut self, cond: Option<Condition>) -> bool {
         let didRet;
         match cond {
@@ -569,116 +625,79 @@ impl CPU {
             None => didRet = true,
         };
 
+        if cond.is_some() {
+            self.internal_cycle();
+        }
+
         if didRet {
             self.pop(RegIndex::PC);
+            self.internal_cycle();
+            self.branched = true;
         }
 
-        // add interrupt stuff later to implement reti instruction
-
         didRet
     }
 
+    // return and enable interrupts immediately, without ei's delay
+    fn reti(&mut self) {
+        self.ret(None);
+        self.ime = true;
+        self.ei_pending = false;
+    }
+
     fn rst(&mut self, addr: u16) {
         self.push(self.regs.pc());
         self.regs.set_pc(addr);
+        self.branched = true;
     }
 
     // rotate reg A left, putting the previous high order bit into carry flag
     fn rlca(&mut self) {
-        let bit7 = self.regs.a() & (1 << 7) > 0;
-        let new_a = (self.regs.a() << 1) | bit7 as u8;
-
+        let new_a = self.rlc(self.regs.a());
         self.regs.set_a(new_a);
-        self.regs.set_cflag(bit7);
+        self.regs.set_zflag(false);
     }
 
     // rotate reg A and carry flag together left
     fn rla(&mut self) {
-        let bit7 = self.regs.a() & (1 << 7) > 0;
-        let new_a = (self.regs.a() << 1) | self.regs.cflag() as u8;
-
+        let new_a = self.rl(self.regs.a());
         self.regs.set_a(new_a);
-        self.regs.set_cflag(bit7);
+        self.regs.set_zflag(false);
     }
 
-    // rotate reg A right, putting the previous high order bit into carry flag
+    // rotate reg A right, putting the previous low order bit into carry flag
     fn rrca(&mut self) {
-        let bit0 = self.regs.a() & 1 > 0;
-        let new_a = (self.regs.a() >> 1) | ((bit0 as u8) << 7);
-
+        let new_a = self.rrc(self.regs.a());
         self.regs.set_a(new_a);
-        self.regs.set_cflag(bit0);
+        self.regs.set_zflag(false);
     }
 
     // rotate reg A and carry flag together right
     fn rra(&mut self) {
-        let bit0 = self.regs.a() & 1 > 0;
-        let new_a = (self.regs.a() >> 1) | ((self.regs.cflag() as u8) << 7);
-
+        let new_a = self.rr(self.regs.a());
         self.regs.set_a(new_a);
-        self.regs.set_cflag(bit0);
+        self.regs.set_zflag(false);
     }
 
-    // converts reg A into a binary coded decimal number
+    // converts reg A into a binary coded decimal number, after an add or sub of two bcd numbers
     fn daa(&mut self) {
-        let a = self.regs.a();
-        let add_to_a: u8;
+        let mut a = self.regs.a();
 
         if self.regs.sflag() {
-            if self.regs.cflag() {
-                if self.regs.hflag() {
-                    add_to_a = 0x9a;
-                } else {
-                    add_to_a = 0xa0;
-                }
-            } else {
-                if self.regs.hflag() {
-                    add_to_a = 0xfa;
-                } else {
-                    add_to_a = 0x00;
-                }
-            }
+            if self.regs.cflag() { a = a.wrapping_sub(0x60); }
+            if self.regs.hflag() { a = a.wrapping_sub(0x06); }
         } else {
-            if self.regs.cflag() {
-                if self.regs.hflag() {
-                    add_to_a = 0x66;
-                } else {
-                    if a & 0x0f <= 0x09 {
-                        add_to_a = 0x60;
-                    } else {
-                        add_to_a = 0x66;
-                    }
-                }
-            } else {
-                if self.regs.hflag() {
-                    if a & 0xf0 <= 0x90 {
-                        add_to_a = 0x06;
-                    } else {
-                        add_to_a = 0x66;
-                        self.regs.set_cflag(true);
-                    }
-                } else {
-                    if a & 0x0f <= 0x09 {
-                        if a & 0xf0 <= 0x90 {
-                            add_to_a = 0x00;
-                        } else {
-                            add_to_a = 0x60;
-                            self.regs.set_cflag(true);
-                        }
-                    } else {
-                        if a & 0xf0 <= 0x80 {
-                            add_to_a = 0x06;
-                        } else {
-                            add_to_a = 0x66;
-                            self.regs.set_cflag(true);
-                        }
-                    }
-                }
+            if self.regs.cflag() || a > 0x99 {
+                a = a.wrapping_add(0x60);
+                self.regs.set_cflag(true);
+            }
+            if self.regs.hflag() || a & 0x0f > 0x09 {
+                a = a.wrapping_add(0x06);
             }
         }
 
-        self.regs.set_a(self.regs.a().wrapping_add(add_to_a));
-        self.regs.set_zflag(self.regs.a() == 0);
+        self.regs.set_a(a);
+        self.regs.set_zflag(a == 0);
         self.regs.set_hflag(false);
     }
 
@@ -704,81 +723,446 @@ impl CPU {
         self.regs.set_sflag(false);
     }
 
+    // cb-prefixed ops are laid out in a grid: bits 0-2 pick the operand, bits 3-5 pick the
+    // shift/rotate op or bit number, and bits 6-7 pick between shift/rotate, bit, res and set.
+    // returns cycles taken including the fetch of the 0xcb prefix
     fn decode_cb(&mut self) -> u8 {
         let op = self.fetch_ins_byte();
+        let bit = (op >> 3) & 0x07;
+        let val = self.cb_operand(op);
+
         match op {
+            0x00..=0x07 => { let res = self.rlc(val); self.set_cb_operand(op, res); },
+            0x08..=0x0f => { let res = self.rrc(val); self.set_cb_operand(op, res); },
+            0x10..=0x17 => { let res = self.rl(val); self.set_cb_operand(op, res); },
+            0x18..=0x1f => { let res = self.rr(val); self.set_cb_operand(op, res); },
+            0x20..=0x27 => { let res = self.sla(val); self.set_cb_operand(op, res); },
+            0x28..=0x2f => { let res = self.sra(val); self.set_cb_operand(op, res); },
+            0x30..=0x37 => { let res = self.swap(val); self.set_cb_operand(op, res); },
+            0x38..=0x3f => { let res = self.srl(val); self.set_cb_operand(op, res); },
+            0x40..=0x7f => { self.bit(bit, val); },
+            0x80..=0xbf => { let res = self.res(bit, val); self.set_cb_operand(op, res); },
+            0xc0..=0xff => { let res = self.set(bit, val); self.set_cb_operand(op, res); },
+        }
 
-            _ => { self.undefined_op(op); 1 },
+        let info = &CB_OPCODES[op as usize];
+        self.apply_flag_effects(info);
+        info.cycles
+    }
+
+    // read the operand of a cb-prefixed op: B, C, D, E, H, L, (HL), A
+    fn cb_operand(&mut self, op: u8) -> u8 {
+        match op & 0x07 {
+            0 => self.regs.b(),
+            1 => self.regs.c(),
+            2 => self.regs.d(),
+            3 => self.regs.e(),
+            4 => self.regs.h(),
+            5 => self.regs.l(),
+            6 => self.read_cycle(self.regs.hl()),
+            _ => self.regs.a(),
         }
     }
 
-    fn rlc(&mut self) {
+    // write back the result of a cb-prefixed op to its operand
+    fn set_cb_operand(&mut self, op: u8, val: u8) {
+        match op & 0x07 {
+            0 => self.regs.set_b(val),
+            1 => self.regs.set_c(val),
+            2 => self.regs.set_d(val),
+            3 => self.regs.set_e(val),
+            4 => self.regs.set_h(val),
+            5 => self.regs.set_l(val),
+            6 => self.write_cycle(self.regs.hl(), val),
+            _ => self.regs.set_a(val),
+        }
+    }
 
+    // set flags shared by all cb shifts and rotates
+    fn set_shift_flags(&mut self, res: u8, carry: bool) {
+        self.regs.set_zflag(res == 0);
+        self.regs.set_sflag(false);
+        self.regs.set_hflag(false);
+        self.regs.set_cflag(carry);
     }
 
-    fn rl(&mut self) {
+    // rotate left, putting the previous high order bit into carry flag
+    fn rlc(&mut self, val: u8) -> u8 {
+        let res = val.rotate_left(1);
+        self.set_shift_flags(res, val & 0x80 != 0);
+        res
+    }
 
+    // rotate left through carry flag
+    fn rl(&mut self, val: u8) -> u8 {
+        let res = (val << 1) | self.regs.cflag() as u8;
+        self.set_shift_flags(res, val & 0x80 != 0);
+        res
     }
 
-    fn sla(&mut self) {
+    // shift left into carry flag, bit 0 is reset
+    fn sla(&mut self, val: u8) -> u8 {
+        let res = val << 1;
+        self.set_shift_flags(res, val & 0x80 != 0);
+        res
+    }
 
+    // rotate right, putting the previous low order bit into carry flag
+    fn rrc(&mut self, val: u8) -> u8 {
+        let res = val.rotate_right(1);
+        self.set_shift_flags(res, val & 1 != 0);
+        res
     }
 
-    fn rrc(&mut self) {
+    // rotate right through carry flag
+    fn rr(&mut self, val: u8) -> u8 {
+        let res = (val >> 1) | ((self.regs.cflag() as u8) << 7);
+        self.set_shift_flags(res, val & 1 != 0);
+        res
+    }
 
+    // shift right into carry flag, bit 7 is kept
+    fn sra(&mut self, val: u8) -> u8 {
+        let res = (val >> 1) | (val & 0x80);
+        self.set_shift_flags(res, val & 1 != 0);
+        res
     }
 
-    fn rr(&mut self) {
+    // swap upper and lower nibbles
+    fn swap(&mut self, val: u8) -> u8 {
+        let res = val.rotate_left(4);
+        self.set_shift_flags(res, false);
+        res
+    }
 
+    // shift right into carry flag, bit 7 is reset
+    fn srl(&mut self, val: u8) -> u8 {
+        let res = val >> 1;
+        self.set_shift_flags(res, val & 1 != 0);
+        res
     }
 
-    fn sra(&mut self) {
+    // test given bit, carry flag is left untouched
+    fn bit(&mut self, bit: u8, val: u8) {
+        self.regs.set_zflag(val & (1 << bit) == 0);
+        self.regs.set_sflag(false);
+        self.regs.set_hflag(true);
+    }
 
+    // reset given bit
+    fn res(&mut self, bit: u8, val: u8) -> u8 {
+        val & !(1 << bit)
     }
 
-    fn swap(&mut self) {
+    // set given bit
+    fn set(&mut self, bit: u8, val: u8) -> u8 {
+        val | (1 << bit)
+    }
 
+    // the opcode fetch already took its cycle. under Stop pc is left pointing at the opcode
+    fn undefined_op(&mut self, op: u8) {
+        match self.illegal_op_policy {
+            IllegalOpPolicy::Lock => self.locked = true,
+            IllegalOpPolicy::Stop => {
+                let pc = self.regs.pc().wrapping_sub(1);
+                self.regs.set_pc(pc);
+                self.fault = Some(IllegalOpcode { opcode: op, pc, bank: self.bus.bank(pc) });
+            },
+        }
     }
 
-    fn srl(&mut self) {
+    // enable interrupts after the next instruction
+    fn ei(&mut self) {
+        self.ei_pending = true;
+    }
 
+    // disable interrupts immediately, cancelling a pending ei
+    fn di(&mut self) {
+        self.ime = false;
+        self.ei_pending = false;
     }
 
-    fn bit(&mut self) {
+    // enter stop mode, see pan docs "using the stop instruction" for the cases below.
+    // stop skips the byte after it unless an interrupt is pending
+    fn stop(&mut self) {
+        let pending = self.bus.pending_interrupts() != 0;
+
+        // the padding byte is skipped over without being read
+        let skip_padding = |cpu: &mut Self| cpu.regs.set_pc(cpu.regs.pc().wrapping_add(1));
+
+        if self.buttons_held() {
+            // stop mode would be left immediately, so it acts like halt instead (or a nop)
+            if !pending {
+                skip_padding(self);
+                self.halted = true;
+            }
+            return
+        }
 
+        if !pending {
+            skip_padding(self);
+        }
+        self.bus.write_byte(0xff04, 0);     // entering stop mode resets DIV
+        self.stopped = true;
     }
 
-    fn res(&mut self) {
+    // halt until an interrupt is pending. if one is already pending with ime reset, halt is
+    // not entered and the next opcode byte is read twice (the halt bug)
+    fn halt(&mut self) {
+        if !self.ime && self.bus.pending_interrupts() != 0 {
+            self.halt_bug = true;
+        } else {
+            self.halted = true;
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::FlatBus;
+
+    // a cpu about to run the given program at 0x100, with the stack at 0xd000
+    fn cpu(program: &[u8]) -> CPU<FlatBus> {
+        let mut bus = FlatBus::init();
+        bus.load(0x100, program);
+        let mut cpu = CPU::with_bus(bus);
+        cpu.regs.set_pc(0x100);
+        cpu.regs.set_sp(0xd000);
+        cpu
+    }
 
+    fn set_interrupts(cpu: &mut CPU<FlatBus>, enable: u8, flags: u8) {
+        cpu.bus.write_byte(0xffff, enable);
+        cpu.bus.write_byte(0xff0f, flags);
     }
 
-    fn set(&mut self) {
+    // a cpu with interrupts enabled, about to run a NOP
+    fn cpu_with_ime(enable: u8, flags: u8) -> CPU<FlatBus> {
+        let mut cpu = cpu(&[0x00]);
+        cpu.set_ime(true);
+        set_interrupts(&mut cpu, enable, flags);
+        cpu
+    }
 
+    #[test]
+    fn ei_takes_effect_after_next_instruction() {
+        let mut cpu = cpu(&[0xfb, 0x00, 0x00]);     // EI, NOP, NOP
+        set_interrupts(&mut cpu, 0x01, 0x01);
+
+        assert_eq!(cpu.step().unwrap().interrupt, None);
+        assert!(!cpu.ime());
+        let step = cpu.step().unwrap();
+        assert_eq!(step.interrupt, None);
+        assert_eq!(step.pc, 0x101);
+        assert!(cpu.ime());
+        assert_eq!(cpu.step().unwrap().interrupt, Some(Interrupt::VBlank));
+        assert_eq!(cpu.regs.pc(), 0x40);
     }
 
-    fn undefined_op(&self, op: u8) {
-        println!("Instruction {:#04x} is undefined!", op);
+    #[test]
+    fn di_cancels_ei() {
+        let mut cpu = cpu(&[0xfb, 0xf3, 0x00]);     // EI, DI, NOP
+        set_interrupts(&mut cpu, 0x01, 0x01);
+        for _ in 0..3 {
+            assert_eq!(cpu.step().unwrap().interrupt, None);
+        }
+        assert!(!cpu.ime());
     }
 
-    // enable interrupts
-    fn ei(&mut self) {
+    #[test]
+    fn priority_order() {
+        let mut cpu = cpu_with_ime(0x1f, 0x1c);     // timer, serial and joypad
+        assert_eq!(cpu.step().unwrap().interrupt, Some(Interrupt::Timer));
+        assert_eq!(cpu.regs.pc(), 0x50);
+        assert_eq!(cpu.bus.read_byte(0xff0f), 0x18);
+        assert!(!cpu.ime());
+
+        // requested but not enabled interrupts are skipped
+        let mut cpu = cpu_with_ime(0x10, 0x11);
+        assert_eq!(cpu.step().unwrap().interrupt, Some(Interrupt::Joypad));
+        assert_eq!(cpu.regs.pc(), 0x60);
+    }
 
+    #[test]
+    fn dispatch_takes_5_cycles() {
+        let mut cpu = cpu_with_ime(0x04, 0x04);
+        cpu.regs.set_pc(0x1234);
+        let step = cpu.step().unwrap();
+        assert_eq!(step.cycles, 5);
+        assert!(step.branch_taken);
+        assert_eq!(cpu.bus.cycles(), 5);
+        assert_eq!(cpu.regs.sp(), 0xcffe);
+        assert_eq!(cpu.bus.read_word(0xcffe), 0x1234);
     }
 
-    // disable interrupts
-    fn di(&mut self) {
+    #[test]
+    fn push_to_ie_changes_the_interrupt() {
+        // with sp at 0 the high byte of pc is pushed to IE, before the interrupt is picked
+        let mut cpu = cpu_with_ime(0x01, 0x01);
+        cpu.regs.set_pc(0x0250);
+        cpu.regs.set_sp(0x0000);
+        let step = cpu.step().unwrap();
+        assert_eq!(step.interrupt, None);
+        assert_eq!(step.cycles, 5);
+        assert_eq!(cpu.regs.pc(), 0x0000);
+        assert_eq!(cpu.bus.read_byte(0xffff), 0x02);
+        assert_eq!(cpu.bus.read_byte(0xff0f), 0x01);   // nothing was acknowledged
+
+        // a lower priority interrupt that is still enabled gets serviced instead
+        let mut cpu = cpu_with_ime(0x03, 0x03);
+        cpu.regs.set_pc(0x0250);
+        cpu.regs.set_sp(0x0000);
+        assert_eq!(cpu.step().unwrap().interrupt, Some(Interrupt::LcdStat));
+        assert_eq!(cpu.regs.pc(), 0x48);
+        assert_eq!(cpu.bus.read_byte(0xff0f), 0x01);
+    }
+
+    #[test]
+    fn reti_enables_interrupts_immediately() {
+        let mut cpu = cpu(&[0xd9]);     // RETI
+        cpu.bus.write_word(0xd000, 0x0200);
+        set_interrupts(&mut cpu, 0x01, 0x01);
+
+        let step = cpu.step().unwrap();
+        assert_eq!(step.cycles, 4);
+        assert!(cpu.ime());
+        assert_eq!(cpu.regs.pc(), 0x0200);
 
+        // unlike after EI, the very next step services the interrupt
+        assert_eq!(cpu.step().unwrap().interrupt, Some(Interrupt::VBlank));
+        assert_eq!(cpu.bus.read_word(cpu.regs.sp()), 0x0200);
     }
 
-    fn stop(&mut self) {
-        if self.fetch_ins_byte() == 0x00 {
-            // stop logic
-            // i don't think this is really necessary though
+    #[test]
+    fn halt_wakes_without_ime() {
+        let mut cpu = cpu(&[0x76, 0x3c]);   // HALT, INC A
+        set_interrupts(&mut cpu, 0x04, 0x00);
+        cpu.step().unwrap();
+        assert!(cpu.halted);
+
+        for _ in 0..3 {
+            let step = cpu.step().unwrap();
+            assert_eq!(step.cycles, 1);
+            assert_eq!(step.bytes(), &[] as &[u8]);
         }
+        assert!(cpu.halted);
+        assert_eq!(cpu.regs.pc(), 0x101);
+
+        // a requested and enabled interrupt wakes it up, but with ime reset it isn't serviced
+        cpu.bus.write_byte(0xff0f, 0x04);
+        cpu.step().unwrap();
+        assert!(!cpu.halted);
+        assert_eq!(cpu.step().unwrap().interrupt, None);
+        assert_eq!(cpu.regs.a(), 1);
+        assert_eq!(cpu.regs.pc(), 0x102);
+        assert_eq!(cpu.bus.read_byte(0xff0f), 0x04);
     }
 
-    fn halt(&mut self) {
-        self.halted = true;
-        // other stuff ?
+    #[test]
+    fn halt_bug() {
+        // an interrupt is already pending with ime reset, so halt isn't entered and the byte
+        // after it is read twice
+        let mut cpu = cpu(&[0x76, 0x3c, 0x00]);     // HALT, INC A, NOP
+        set_interrupts(&mut cpu, 0x04, 0x04);
+        cpu.step().unwrap();
+        assert!(!cpu.halted);
+        assert_eq!(cpu.step().unwrap().pc, 0x101);
+        assert_eq!(cpu.regs.pc(), 0x101);
+        assert_eq!(cpu.step().unwrap().pc, 0x101);
+        assert_eq!(cpu.regs.pc(), 0x102);
+        assert_eq!(cpu.regs.a(), 2);
+    }
+
+    #[test]
+    fn halt_with_ime() {
+        let mut cpu = cpu_with_ime(0x04, 0x00);
+        cpu.bus.load(0x100, &[0x76]);
+        cpu.step().unwrap();
+        assert!(cpu.halted);
+
+        // waking takes a cycle, then the interrupt is serviced returning past the halt
+        cpu.bus.write_byte(0xff0f, 0x04);
+        assert_eq!(cpu.step().unwrap().interrupt, None);
+        assert_eq!(cpu.step().unwrap().interrupt, Some(Interrupt::Timer));
+        assert_eq!(cpu.bus.read_word(cpu.regs.sp()), 0x101);
+    }
+
+    #[test]
+    fn ei_halt_with_interrupt_pending() {
+        // halt runs with ime still reset so the halt bug triggers, but the interrupt is serviced
+        // before the repeated fetch and returns to the halt itself
+        let mut cpu = cpu(&[0xfb, 0x76, 0x3c]);     // EI, HALT, INC A
+        set_interrupts(&mut cpu, 0x04, 0x04);
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert!(cpu.ime());
+        assert_eq!(cpu.step().unwrap().interrupt, Some(Interrupt::Timer));
+        assert_eq!(cpu.bus.read_word(cpu.regs.sp()), 0x101);
+        assert!(!cpu.halt_bug);
+
+        // the handler's first byte is only read once
+        cpu.bus.load(0x50, &[0x3c, 0x3c]);
+        cpu.step().unwrap();
+        assert_eq!(cpu.regs.pc(), 0x51);
+    }
+
+    // STOP followed by its padding byte and INC A, with no buttons pressed
+    fn stop_cpu(enable: u8, flags: u8) -> CPU<FlatBus> {
+        let mut cpu = cpu(&[0x10, 0x00, 0x3c]);
+        cpu.bus.write_byte(0xff00, 0xff);
+        cpu.bus.write_byte(0xff04, 0x12);
+        set_interrupts(&mut cpu, enable, flags);
+        cpu
+    }
+
+    #[test]
+    fn stop() {
+        let mut cpu = stop_cpu(0x00, 0x00);
+        cpu.step().unwrap();
+        assert!(cpu.stopped);
+        assert_eq!(cpu.regs.pc(), 0x102);
+        assert_eq!(cpu.bus.read_byte(0xff04), 0);
+
+        // interrupts don't end stop mode, only buttons do
+        set_interrupts(&mut cpu, 0x1f, 0x1f);
+        cpu.step().unwrap();
+        assert!(cpu.stopped);
+        assert_eq!(cpu.regs.pc(), 0x102);
+        cpu.bus.write_byte(0xff00, 0xfe);
+        cpu.step().unwrap();
+        assert!(!cpu.stopped);
+        cpu.step().unwrap();
+        assert_eq!(cpu.regs.a(), 1);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn stop_with_interrupt_pending() {
+        // stop mode is entered, but the padding byte isn't skipped
+        let mut cpu = stop_cpu(0x04, 0x04);
+        cpu.step().unwrap();
+        assert!(cpu.stopped);
+        assert_eq!(cpu.regs.pc(), 0x101);
+        assert_eq!(cpu.bus.read_byte(0xff04), 0);
+    }
+
+    #[test]
+    fn stop_with_button_held() {
+        // without an interrupt pending it acts like halt, skipping the padding byte
+        let mut cpu = stop_cpu(0x04, 0x00);
+        cpu.bus.write_byte(0xff00, 0xfe);
+        cpu.step().unwrap();
+        assert!(!cpu.stopped);
+        assert!(cpu.halted);
+        assert_eq!(cpu.regs.pc(), 0x102);
+        assert_eq!(cpu.bus.read_byte(0xff04), 0x12);
+
+        // with one pending it's a 1 byte nop
+        let mut cpu = stop_cpu(0x04, 0x04);
+        cpu.bus.write_byte(0xff00, 0xfe);
+        cpu.step().unwrap();
+        assert!(!cpu.stopped);
+        assert!(!cpu.halted);
+        assert_eq!(cpu.regs.pc(), 0x101);
+        assert_eq!(cpu.bus.read_byte(0xff04), 0x12);
+    }
+}
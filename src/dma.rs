@@ -0,0 +1,120 @@
+// OAM DMA, started by writing the upper byte of a source address to FF46. it copies 160 bytes from
+// XX00-XX9F to OAM at FE00-FE9F, one per machine cycle, during which the cpu can't use the bus
+pub struct Dma {
+    reg: u8,                // FF46 as last written
+    active: Option<u16>,    // source of the byte to copy next
+    count: u8,              // bytes copied so far
+    starting: Option<(u16, u8)>,  // requested transfer and machine cycles until it starts
+}
+
+pub const OAM_SIZE: u8 = 0xa0;
+
+impl Dma {
+    pub fn init() -> Dma {
+        Dma {
+            reg: 0xff,
+            active: None,
+            count: 0,
+            starting: None,
+        }
+    }
+
+    pub fn read_reg(&self) -> u8 {
+        self.reg
+    }
+
+    // sources from E000 up read the work ram behind them instead of echo ram, OAM and io
+    pub fn write_reg(&mut self, val: u8) {
+        self.reg = val;
+        let source = (val as u16) << 8;
+        let source = if source >= 0xe000 { source - 0x2000 } else { source };
+        // the rest of the cycle of the write, then a cycle of setup
+        self.starting = Some((source, 2));
+    }
+
+    // whether the cpu is locked out of everything but io and high ram
+    pub fn blocking(&self) -> bool {
+        self.active.is_some()
+    }
+
+    // advance one machine cycle, returning (source address, OAM offset) of the byte to copy.
+    // starting a new transfer while one is running lets the old one keep copying until the new
+    // one is set up, which then starts over from the beginning
+    pub fn tick(&mut self) -> Option<(u16, u8)> {
+        let copy = self.active.map(|source| (source, self.count));
+        if let Some(source) = self.active {
+            self.count += 1;
+            self.active = if self.count < OAM_SIZE { Some(source + 1) } else { None };
+        }
+
+        match self.starting {
+            Some((source, 1)) => {
+                self.starting = None;
+                self.active = Some(source);
+                self.count = 0;
+            },
+            Some((source, delay)) => self.starting = Some((source, delay - 1)),
+            None => (),
+        }
+        copy
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copies_160_bytes_after_the_delay() {
+        let mut dma = Dma::init();
+        dma.write_reg(0xc1);
+        assert_eq!(dma.read_reg(), 0xc1);
+        assert_eq!(dma.tick(), None);
+        assert!(!dma.blocking());
+        assert_eq!(dma.tick(), None);
+        assert!(dma.blocking());
+
+        for offset in 0..OAM_SIZE {
+            assert_eq!(dma.tick(), Some((0xc100 + offset as u16, offset)));
+        }
+        assert!(!dma.blocking());
+        assert_eq!(dma.tick(), None);
+    }
+
+    #[test]
+    fn sources_past_work_ram() {
+        let mut dma = Dma::init();
+        dma.write_reg(0xe1);
+        dma.tick();
+        dma.tick();
+        assert_eq!(dma.tick(), Some((0xc100, 0)));
+
+        dma.write_reg(0xfe);
+        dma.tick();
+        dma.tick();
+        assert_eq!(dma.tick(), Some((0xde00, 0)));
+
+        dma.write_reg(0xdf);
+        dma.tick();
+        dma.tick();
+        assert_eq!(dma.tick(), Some((0xdf00, 0)));
+    }
+
+    #[test]
+    fn restart_while_running() {
+        let mut dma = Dma::init();
+        dma.write_reg(0xc0);
+        for _ in 0..12 {
+            dma.tick();
+        }
+
+        // the old transfer goes on until the new one is set up
+        dma.write_reg(0xd0);
+        assert_eq!(dma.tick(), Some((0xc00a, 10)));
+        assert_eq!(dma.tick(), Some((0xc00b, 11)));
+        for offset in 0..OAM_SIZE {
+            assert_eq!(dma.tick(), Some((0xd000 + offset as u16, offset)));
+        }
+        assert!(!dma.blocking());
+    }
+}
@@ -1,11 +1,26 @@
-use crate::regs::Regs;
-use crate::mmu::MMU;
-use std::mem::transmute;
+use crate::interrupts::{Interrupt, Interrupts};
 
-const B_WIDTH: usize = 256;  // Background
-const B_HEIGHT: usize = 256;
-const W_WIDTH: usize = 160;  // Windows
-const W_HEIGHT: usize = 144;
+// the part of the background that ends up on the lcd
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+
+// clocks spent in each mode on a visible line, and in a whole line
+const OAM_CLOCKS: u32 = 80;
+const VRAM_CLOCKS: u32 = 172;
+const HBLANK_CLOCKS: u32 = 204;
+const LINE_CLOCKS: u32 = 456;
+const LINES: u8 = 154;  // 144 visible and 10 in vblank
+
+// tile data at 8000-97FF holds 384 tiles of 16 bytes, the rest of vram is the 2 tile maps
+const TILES: usize = 384;
+const TILE_DATA_END: usize = TILES * 16;
+
+// OAM at FE00-FE9F holds 40 sprites of 4 bytes: y+16, x+8, tile and attributes
+const SPRITES: usize = 40;
+const SPRITES_PER_LINE: usize = 10;
+
+// the 4 shades of the lcd from lightest to darkest, as 0RGB
+const SHADES: [u32; 4] = [0xe0f8d0, 0x88c070, 0x346856, 0x081820];
 
 const modeOAM : u8 = 2;
 const modeVRAM : u8 = 3;
@@ -18,142 +33,543 @@ pub struct GPU{
     LCDC : u8,  // LCD Control byte FF40
     STAT : u8,  // LCDC Status FF41
     scan_line : u8, // Current scan line
+    LYC : u8,  // LY compare, 0xFF45
     BGP : u8,  // background palette, 0xFF47
+    OBP0 : u8,  // sprite palettes, 0xFF48 and 0xFF49
+    OBP1 : u8,
+    WY : u8,  // window position, 0xFF4A and 0xFF4B
+    WX : u8,
     cycles : u32,
     mode : u8,
 
-    // each tile is 16 bytes
-    tile_set : Vec<u128>,   // pick one between 0 or 1
-    tile_set_0 : Vec<u128>,  // 8000-8FFF, 0-255
-    tile_set_1 : Vec<u128>,  // 8800-97FF, -128-127
-    tile_set_colored : Vec<u64>,
+    vram : [u8; 0x2000],    // 8000-9FFF
+    oam : [u8; 0xa0],       // FE00-FE9F
+    // each tile is 8 rows of 2 bytes, the first with bit 0 and the second with bit 1 of the
+    // color of each pixel, leftmost pixel in bit 7. decoded to one color per pixel when used
+    tiles : Vec<[u8; 64]>,
+    tiles_dirty : Vec<bool>,    // written since it was last decoded
 
-    background : Vec<u32>,
-    window : Vec<u32>,
+    window_triggered : bool,    // LY matched WY at some point in this frame
+    window_line : u8,   // line of the window to draw next, only counts lines it was drawn on
+
+    frame : Vec<u32>,   // what is on the lcd, one 0RGB pixel per dot
+    frame_ready : bool, // a whole frame has been drawn since the last take_frame
+    line_sprites : Vec<usize>,  // sprites found on the current line by the oam search, in drawing priority
 }
 
 impl GPU {
 
     pub fn init() -> GPU {
-        let mut gpu = GPU{
+        GPU{
             SX : 0,
             SY : 0,
             LCDC : 0,
             STAT : 0,
             scan_line : 0,
+            LYC : 0,
             BGP : 0,
+            OBP0 : 0,
+            OBP1 : 0,
+            WY : 0,
+            WX : 0,
             cycles : 0,
             mode : 0,
 
-            tile_set : vec![0; 256],
-            tile_set_0 : vec![0; 256],
-            tile_set_1 : vec![0; 256],
-            tile_set_colored : vec![0; 256],
-            background : vec![0; B_WIDTH * B_HEIGHT],
-            window : vec![0; W_WIDTH * W_HEIGHT],
-        };
-        gpu
+            vram : [0; 0x2000],
+            oam : [0; 0xa0],
+            tiles : vec![[0; 64]; TILES],
+            tiles_dirty : vec![false; TILES],
+            window_triggered : false,
+            window_line : 0,
+
+            frame : vec![SHADES[0]; SCREEN_WIDTH * SCREEN_HEIGHT],
+            frame_ready : false,
+            line_sprites : Vec::with_capacity(SPRITES_PER_LINE),
+        }
+    }
+
+    pub fn read_vram(&self, addr : u16) -> u8 {
+        self.vram[(addr - 0x8000) as usize]
+    }
+
+    pub fn write_vram(&mut self, addr : u16, val : u8) {
+        let i = (addr - 0x8000) as usize;
+        self.vram[i] = val;
+        if i < TILE_DATA_END {
+            self.tiles_dirty[i / 16] = true;
+        }
+    }
+
+    pub fn read_oam(&self, addr : u16) -> u8 {
+        self.oam[(addr - 0xfe00) as usize]
     }
 
-    pub fn update_tiles(&mut self) {
-        // update tile sets
-        for i in 0..255 {
-            for j in 0..15 {//  each tile is 16 bytes, !!!!assuming little endian here for now!!!!!
-                // self.tile_set_0[i] += self.mmu.read_byte(0x8000 + (16 * i + j) as u16) as u128 * 2^(j as u128);
-                // self.tile_set_1[i] += self.mmu.read_byte(0x8800 + (16 * i + j) as u16) as u128 * 2^(j as u128);
+    pub fn write_oam(&mut self, addr : u16, val : u8) {
+        self.oam[(addr - 0xfe00) as usize] = val;
+    }
+
+    // colors of the pixels of a tile in 8000-97FF, decoding it again if it changed
+    fn tile(&mut self, index : usize) -> &[u8; 64] {
+        if self.tiles_dirty[index] {
+            self.tiles_dirty[index] = false;
+            let data = &self.vram[index * 16..index * 16 + 16];
+            let tile = &mut self.tiles[index];
+            for row in 0..8 {
+                let (lo, hi) = (data[row * 2], data[row * 2 + 1]);
+                for x in 0..8 {
+                    let bit = 7 - x;
+                    tile[row * 8 + x] = ((hi >> bit) & 1) << 1 | ((lo >> bit) & 1);
+                }
             }
         }
-        // self.LCDC = self.mmu.read_byte(0xff40);
-        // self.STAT = self.mmu.read_byte(0xff41);
-        // self.BGP = self.mmu.read_byte(0xff47);
+        &self.tiles[index]
+    }
+
+    // the last frame drawn, SCREEN_WIDTH * SCREEN_HEIGHT pixels
+    pub fn frame(&self) -> &[u32] {
+        &self.frame
+    }
+
+    // whether a new frame was finished since the last call
+    pub fn take_frame(&mut self) -> bool {
+        let ready = self.frame_ready;
+        self.frame_ready = false;
+        ready
+    }
+
+    // start on the given line and mode, for starting in the state a boot rom leaves behind
+    pub fn set_line(&mut self, line : u8, mode : u8) {
+        self.scan_line = line;
+        self.mode = mode;
+        self.cycles = 0;
+        self.window_triggered = false;
+        self.window_line = 0;
+    }
+
+    // whether the gpu is reading OAM, in which case the cpu can't
+    pub fn oam_locked(&self) -> bool {
+        self.lcd_on() && (self.mode == modeOAM || self.mode == modeVRAM)
+    }
+
+    fn lcd_on(&self) -> bool {
+        self.LCDC & 0x80 != 0
+    }
+
+    // LCD registers 0xFF40-0xFF4B
+    pub fn read_reg(&self, addr : u16) -> u8 {
+        match addr {
+            0xff40 => self.LCDC,
+            0xff41 => {
+                // bit 7 is unused, and the mode and coincidence bits are read-only
+                let coincidence = ((self.scan_line == self.LYC) as u8) << 2;
+                let mode = if self.lcd_on() { self.mode } else { modeHBLANK };
+                0x80 | (self.STAT & 0x78) | coincidence | mode
+            },
+            0xff42 => self.SY,
+            0xff43 => self.SX,
+            0xff44 => self.scan_line,
+            0xff45 => self.LYC,
+            0xff47 => self.BGP,
+            0xff48 => self.OBP0,
+            0xff49 => self.OBP1,
+            0xff4a => self.WY,
+            0xff4b => self.WX,
+            _ => 0xff,
+        }
+    }
+
+    pub fn write_reg(&mut self, addr : u16, val : u8) {
+        match addr {
+            0xff40 => {
+                // turning the lcd off resets it to the start of the frame, and it starts drawing
+                // from there once turned back on
+                let was_on = self.lcd_on();
+                self.LCDC = val;
+                if was_on && !self.lcd_on() {
+                    self.set_line(0, modeHBLANK);
+                    for pixel in self.frame.iter_mut() {
+                        *pixel = SHADES[0];     // a turned off lcd is blank
+                    }
+                } else if !was_on && self.lcd_on() {
+                    self.set_line(0, modeOAM);
+                }
+            },
+            0xff41 => self.STAT = val & 0x78,
+            0xff42 => self.SY = val,
+            0xff43 => self.SX = val,
+            0xff44 => (),   // LY is read-only
+            0xff45 => self.LYC = val,
+            0xff47 => self.BGP = val,
+            0xff48 => self.OBP0 = val,
+            0xff49 => self.OBP1 = val,
+            0xff4a => self.WY = val,
+            0xff4b => self.WX = val,
+            _ => (),
+        }
+    }
 
-        let tile_set_index : bool = (self.LCDC & 0b0000_1000) == 0b0000_1000;  // tile set 0 or 1
-        if (tile_set_index == true){   // using tile set 1
-            self.tile_set = self.tile_set_1.clone();
+    // tile of a number in a tile map. with LCDC bit 4 set tiles 0-255 are at 8000-8FFF,
+    // otherwise the number is signed and tile 0 is at 9000
+    fn tile_index(&self, tile : u8) -> usize {
+        if self.LCDC & 0x10 != 0 {
+            tile as usize
         } else {
-            self.tile_set = self.tile_set_0.clone();
-        }
-
-        for i in 0..255 {
-            let byte_array : [u8; 16] = unsafe{ transmute(self.tile_set[i].to_be())};
-            for j in 0..7{
-                let byte1 : u8 = byte_array[j] as u8;
-                let byte2 : u8 = byte_array[j+8] as u8;
-                //println!("{:b}", byte1);
-                //println!("{:b}", byte2);
-
-                let x0 : u8 = ((byte1 & 0b0000_0001)> 0) as u8 * 2 + ((byte2 & 0b0000_0001) > 0) as u8;
-                let x1 : u8 = ((byte1 & 0b0000_0010)> 0) as u8 * 2 + ((byte2 & 0b0000_0010) > 0) as u8;
-                let x2 : u8 = ((byte1 & 0b0000_0100)> 0) as u8 * 2 + ((byte2 & 0b0000_0100) > 0) as u8;
-                let x3 : u8 = ((byte1 & 0b0000_1000)> 0) as u8 * 2 + ((byte2 & 0b0000_1000) > 0) as u8;
-                let x4 : u8 = ((byte1 & 0b0001_0000)> 0) as u8 * 2 + ((byte2 & 0b0001_0000) > 0) as u8;
-                let x5 : u8 = ((byte1 & 0b0010_0000)> 0) as u8 * 2 + ((byte2 & 0b0010_0000) > 0) as u8;
-                let x6 : u8 = ((byte1 & 0b0100_0000)> 0) as u8 * 2 + ((byte2 & 0b0100_0000) > 0) as u8;
-                let x7 : u8 = ((byte1 & 0b1000_0000)> 0) as u8 * 2 + ((byte2 & 0b1000_0000) > 0) as u8;
-
-                self.background[(i - i%32) * 64 + (i % 32) * 8 + j * 256 + 0] = x0 as u32;
-                self.background[(i - i%32) * 64 + (i % 32) * 8 + j * 256 + 1] = x1 as u32;
-                self.background[(i - i%32) * 64 + (i % 32) * 8 + j * 256 + 2] = x2 as u32;
-                self.background[(i - i%32) * 64 + (i % 32) * 8 + j * 256 + 3] = x3 as u32;
-                self.background[(i - i%32) * 64 + (i % 32) * 8 + j * 256 + 4] = x4 as u32;
-                self.background[(i - i%32) * 64 + (i % 32) * 8 + j * 256 + 5] = x5 as u32;
-                self.background[(i - i%32) * 64 + (i % 32) * 8 + j * 256 + 6] = x6 as u32;
-                self.background[(i - i%32) * 64 + (i % 32) * 8 + j * 256 + 7] = x7 as u32;
+            (256 + tile as i8 as i16) as usize
+        }
+    }
+
+    // palette registers map each of the 4 colors in a tile to a shade, 2 bits per color
+    fn shade(palette : u8, color : u8) -> u32 {
+        SHADES[((palette >> (color * 2)) & 0x03) as usize]
+    }
+
+    // color of a pixel of the 256x256 picture made by one of the tile maps
+    fn map_color(&mut self, map : usize, x : usize, y : usize) -> u8 {
+        let tile = self.tile_index(self.vram[map + (y / 8) * 32 + x / 8]);
+        self.tile(tile)[(y % 8) * 8 + x % 8]
+    }
+
+    // sprites are 8x16 with LCDC bit 2 set, 8x8 otherwise
+    fn sprite_height(&self) -> i32 {
+        if self.LCDC & 0x04 != 0 { 16 } else { 8 }
+    }
+
+    // the oam search of mode 2 picks the first 10 sprites in OAM that cover the current line,
+    // whether they end up on the screen horizontally or not
+    fn scan_oam(&mut self) {
+        let line = self.scan_line as i32 + 16;
+        let height = self.sprite_height();
+        self.line_sprites.clear();
+        for i in 0..SPRITES {
+            let y = self.oam[i * 4] as i32;
+            if line >= y && line < y + height {
+                self.line_sprites.push(i);
+                if self.line_sprites.len() == SPRITES_PER_LINE {
+                    break;
+                }
             }
         }
 
+        // on the dmg the sprite further left is drawn on top of the others, or the one that
+        // comes first in OAM when they are at the same x. the sort is stable so it keeps that
+        let oam = &self.oam;
+        self.line_sprites.sort_by_key(|&i| oam[i * 4 + 1]);
     }
 
-    pub fn output(&mut self) -> Vec<u32>{
-        // print testing
-        /*for tile in 0..255 {
-            let byte_array : [u8; 16] = unsafe{ transmute(self.tile_set[tile].to_be())};
-            //println!("tile num: {}", tile);
-            for line in 0..7 {
-                //println!("{:b}", byte_array[line]);
+    // draw the current line into the frame: the background scrolled by SCX/SCY, the window
+    // covering it from WX-7, WY to the bottom right corner of the screen, then the sprites
+    fn render_line(&mut self) {
+        let line = self.scan_line as usize;
+        let start = line * SCREEN_WIDTH;
+
+        // the window only starts once LY and WY have matched during the frame, so moving WY
+        // down later in the frame doesn't make it appear
+        if self.scan_line == self.WY {
+            self.window_triggered = true;
+        }
+
+        // color of each background or window pixel before the palette, sprites need it to
+        // know whether they are behind it
+        let mut colors = [0u8; SCREEN_WIDTH];
+
+        // with LCDC bit 0 clear the background and window are blank
+        if self.LCDC & 0x01 == 0 {
+            for pixel in self.frame[start..start + SCREEN_WIDTH].iter_mut() {
+                *pixel = SHADES[0];
             }
-        }*/
+        } else {
+            self.render_background(&mut colors);
+        }
 
-        // just a wrapper function
-        self.background.clone()
+        if self.LCDC & 0x02 != 0 {
+            self.render_sprites(&colors);
+        }
     }
 
-    pub fn step(&mut self, cycle_increase : u32) {
-        self.cycles += cycle_increase as u32;
-        if (self.mode == modeOAM){
-            if (self.cycles >= 80){
-                self.cycles = 0;
-                self.mode = modeVRAM;
+    fn render_background(&mut self, colors : &mut [u8; SCREEN_WIDTH]) {
+        let start = self.scan_line as usize * SCREEN_WIDTH;
+
+        // with WX below 7 the window still starts at the left edge, but with its first 7-WX
+        // columns cut off. past WX 166 it is off the screen
+        let window_x = self.WX as i32 - 7;
+        let window = self.LCDC & 0x20 != 0 && self.window_triggered && self.WX <= 166;
+
+        let bg_map = if self.LCDC & 0x08 != 0 { 0x1c00 } else { 0x1800 };
+        let window_map = if self.LCDC & 0x40 != 0 { 0x1c00 } else { 0x1800 };
+        let y = self.scan_line.wrapping_add(self.SY) as usize;
+        for (x, color) in colors.iter_mut().enumerate() {
+            *color = if window && x as i32 >= window_x {
+                let window_line = self.window_line as usize;
+                self.map_color(window_map, (x as i32 - window_x) as usize, window_line)
+            } else {
+                let bg_x = (x as u8).wrapping_add(self.SX) as usize;
+                self.map_color(bg_map, bg_x, y)
+            };
+            self.frame[start + x] = GPU::shade(self.BGP, *color);
+        }
+
+        // the window keeps its own line count, so hiding it for some lines makes it continue
+        // where it left off instead of skipping lines
+        if window {
+            self.window_line += 1;
+        }
+    }
+
+    // sprite attributes: bit 7 puts background colors 1-3 in front of the sprite, bits 6 and 5
+    // flip it vertically and horizontally, and bit 4 picks OBP1 over OBP0
+    fn render_sprites(&mut self, colors : &[u8; SCREEN_WIDTH]) {
+        let start = self.scan_line as usize * SCREEN_WIDTH;
+        let height = self.sprite_height();
+
+        // a pixel belongs to the sprite with the highest priority that isn't transparent there,
+        // even if the background then hides that sprite
+        let mut taken = [false; SCREEN_WIDTH];
+        for n in 0..self.line_sprites.len() {
+            let i = self.line_sprites[n] * 4;
+            let y = self.oam[i] as i32 - 16;
+            let x = self.oam[i + 1] as i32 - 8;
+            let attrs = self.oam[i + 3];
+
+            let mut row = self.scan_line as i32 - y;
+            if attrs & 0x40 != 0 {
+                row = height - 1 - row;
             }
-        } else if (self.mode == modeVRAM) {
-            if (self.cycles >= 172) {
-                self.mode = modeHBLANK;
+            // sprites always use 8000 addressing, and 8x16 ones ignore bit 0 of the tile number
+            let tile = if height == 16 {
+                (self.oam[i + 2] & 0xfe) as usize + (row / 8) as usize
+            } else {
+                self.oam[i + 2] as usize
+            };
+            let row = (row % 8) as usize;
+            let palette = if attrs & 0x10 != 0 { self.OBP1 } else { self.OBP0 };
 
-                // render the line on frame buffer using the previous methods
-                
+            for col in 0..8 {
+                let screen_x = x + col as i32;
+                if screen_x < 0 || screen_x >= SCREEN_WIDTH as i32 || taken[screen_x as usize] {
+                    continue;
+                }
+                let screen_x = screen_x as usize;
+                let tile_x = if attrs & 0x20 != 0 { 7 - col } else { col };
+                let color = self.tile(tile)[row * 8 + tile_x];
+                if color == 0 {
+                    continue;   // color 0 is transparent in sprites
+                }
+                taken[screen_x] = true;
+                if attrs & 0x80 != 0 && colors[screen_x] != 0 {
+                    continue;
+                }
+                self.frame[start + screen_x] = GPU::shade(palette, color);
             }
+        }
+    }
 
-        } else if (self.mode == modeHBLANK) {
-            if (self.cycles >= 204) {
-                self.cycles = 0;
+    // advance by some clocks. each visible line goes through OAM search, drawing and hblank,
+    // and is put in the frame at the end of drawing. after line 143 comes vblank
+    pub fn step(&mut self, cycle_increase : u32, intr : &mut Interrupts) {
+        if !self.lcd_on() {
+            return;
+        }
+
+        self.cycles += cycle_increase;
+        if self.mode == modeOAM {
+            if self.cycles >= OAM_CLOCKS {
+                self.cycles -= OAM_CLOCKS;
+                self.mode = modeVRAM;
+                self.scan_oam();
+            }
+        } else if self.mode == modeVRAM {
+            if self.cycles >= VRAM_CLOCKS {
+                self.cycles -= VRAM_CLOCKS;
+                self.mode = modeHBLANK;
+                self.render_line();
+            }
+        } else if self.mode == modeHBLANK {
+            if self.cycles >= HBLANK_CLOCKS {
+                self.cycles -= HBLANK_CLOCKS;
                 self.scan_line += 1;
-                if (self.scan_line == 143){
+                if self.scan_line as usize == SCREEN_HEIGHT {
                     self.mode = modeVBLANK;
+                    self.frame_ready = true;
+                    intr.request(Interrupt::VBlank);
                 } else {
                     self.mode = modeOAM;
                 }
             }
-        } else if (self.mode == modeVBLANK) {
-            //println!("vblank mode");
-            self.update_tiles();
-            if (self.cycles >= 456) {
-                self.cycles = 0;
-                self.scan_line += 1;
-                if (self.scan_line > 153) {
-                    self.mode = modeOAM;
-                    self.scan_line = 0;
-                }
+        } else if self.mode == modeVBLANK && self.cycles >= LINE_CLOCKS {
+            self.cycles -= LINE_CLOCKS;
+            self.scan_line += 1;
+            if self.scan_line == LINES {
+                self.mode = modeOAM;
+                self.scan_line = 0;
+                self.window_triggered = false;
+                self.window_line = 0;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // tile 1 is solid color 1, 2 solid color 3, 3 solid color 2, 4 has only its top left pixel
+    // in color 2 and the rest are blank. both maps are all tile 0 and the palettes map every
+    // color to its own shade, except OBP1 which reverses them
+    fn gpu() -> GPU {
+        let mut gpu = GPU::init();
+        for (tile, color) in [(1, 1), (2, 3), (3, 2)] {
+            for row in 0..8 {
+                let addr = 0x8000 + tile * 16 + row * 2;
+                gpu.write_vram(addr, if color & 1 != 0 { 0xff } else { 0x00 });
+                gpu.write_vram(addr + 1, if color & 2 != 0 { 0xff } else { 0x00 });
+            }
+        }
+        gpu.write_vram(0x8041, 0x80);
+        gpu.write_reg(0xff47, 0xe4);
+        gpu.write_reg(0xff48, 0xe4);
+        gpu.write_reg(0xff49, 0x1b);
+        gpu
+    }
+
+    fn sprite(gpu: &mut GPU, index: u16, y: u8, x: u8, tile: u8, attrs: u8) {
+        let addr = 0xfe00 + index * 4;
+        gpu.write_oam(addr, y);
+        gpu.write_oam(addr + 1, x);
+        gpu.write_oam(addr + 2, tile);
+        gpu.write_oam(addr + 3, attrs);
+    }
+
+    // turn the lcd on with the given LCDC and draw a whole frame
+    fn frame(gpu: &mut GPU, lcdc: u8) -> Vec<u32> {
+        let mut intr = Interrupts::init();
+        gpu.write_reg(0xff40, lcdc);
+        while !gpu.take_frame() {
+            gpu.step(4, &mut intr);
+        }
+        gpu.frame().to_vec()
+    }
+
+    fn pixel(frame: &[u32], x: usize, y: usize) -> u32 {
+        frame[y * SCREEN_WIDTH + x]
+    }
+
+    #[test]
+    fn window_line_counter() {
+        // the first row of the window is tile 2, the rest blank
+        let mut gpu = gpu();
+        for addr in 0x9c00..0x9c20 {
+            gpu.write_vram(addr, 2);
+        }
+        gpu.write_reg(0xff4a, 0);
+        gpu.write_reg(0xff4b, 7);
+
+        // hide the window on lines 4 to 19
+        let mut intr = Interrupts::init();
+        gpu.write_reg(0xff40, 0xf1);
+        while !gpu.take_frame() {
+            if gpu.read_reg(0xff41) & 0x03 == modeOAM {
+                let line = gpu.read_reg(0xff44);
+                gpu.write_reg(0xff40, if (4..20).contains(&line) { 0xd1 } else { 0xf1 });
             }
+            gpu.step(4, &mut intr);
         }
+        let frame = gpu.frame();
+        assert_eq!(pixel(frame, 0, 3), SHADES[3]);
+        assert_eq!(pixel(frame, 0, 10), SHADES[0]);
+
+        // it picks up again at its line 4, not at line 20
+        assert_eq!(pixel(frame, 0, 20), SHADES[3]);
+        assert_eq!(pixel(frame, 159, 23), SHADES[3]);
+        assert_eq!(pixel(frame, 0, 24), SHADES[0]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn ten_sprites_per_line() {
+        // the first sprite is off the left edge, but still one of the 10
+        let mut gpu = gpu();
+        sprite(&mut gpu, 0, 16, 0, 1, 0);
+        for k in 0..10 {
+            sprite(&mut gpu, k + 1, 16, 8 + 12 * k as u8, 1, 0);
+        }
+        // the next line only has the last one
+        sprite(&mut gpu, 11, 24, 8 + 12 * 9, 1, 0);
+
+        let frame = frame(&mut gpu, 0x93);
+        assert_eq!(pixel(&frame, 12 * 8, 0), SHADES[1]);
+        assert_eq!(pixel(&frame, 12 * 9, 0), SHADES[0]);
+        assert_eq!(pixel(&frame, 12 * 9, 8), SHADES[1]);
+    }
+
+    #[test]
+    fn sprite_priority() {
+        let mut gpu = gpu();
+        // the sprite further left is on top, even when it comes later in OAM
+        sprite(&mut gpu, 0, 16, 12, 2, 0);
+        sprite(&mut gpu, 1, 16, 8, 1, 0);
+        // at the same x the one first in OAM is
+        sprite(&mut gpu, 2, 24, 8, 2, 0);
+        sprite(&mut gpu, 3, 24, 8, 1, 0);
+        // except where that one is transparent
+        sprite(&mut gpu, 4, 32, 8, 4, 0);
+        sprite(&mut gpu, 5, 32, 8, 1, 0);
+
+        let frame = frame(&mut gpu, 0x93);
+        assert_eq!(pixel(&frame, 4, 0), SHADES[1]);
+        assert_eq!(pixel(&frame, 8, 0), SHADES[3]);
+        assert_eq!(pixel(&frame, 0, 8), SHADES[3]);
+        assert_eq!(pixel(&frame, 0, 16), SHADES[2]);
+        assert_eq!(pixel(&frame, 1, 16), SHADES[1]);
+    }
+
+    #[test]
+    fn tall_sprites() {
+        let mut gpu = gpu();
+        // tile 3 draws tiles 2 and 3 as well
+        sprite(&mut gpu, 0, 16, 8, 3, 0);
+        // flipped the bottom tile ends up on top
+        sprite(&mut gpu, 1, 16, 16, 2, 0x40);
+
+        let frame = frame(&mut gpu, 0x97);
+        assert_eq!(pixel(&frame, 0, 0), SHADES[3]);
+        assert_eq!(pixel(&frame, 0, 15), SHADES[2]);
+        assert_eq!(pixel(&frame, 0, 16), SHADES[0]);
+        assert_eq!(pixel(&frame, 8, 0), SHADES[2]);
+        assert_eq!(pixel(&frame, 8, 15), SHADES[3]);
+    }
+
+    #[test]
+    fn flips() {
+        let mut gpu = gpu();
+        sprite(&mut gpu, 0, 16, 8, 4, 0);
+        sprite(&mut gpu, 1, 16, 16, 4, 0x20);
+        sprite(&mut gpu, 2, 16, 24, 4, 0x40);
+        sprite(&mut gpu, 3, 16, 32, 4, 0x60);
+        sprite(&mut gpu, 4, 16, 40, 4, 0x10);
+
+        let frame = frame(&mut gpu, 0x93);
+        assert_eq!(pixel(&frame, 0, 0), SHADES[2]);
+        assert_eq!(pixel(&frame, 15, 0), SHADES[2]);
+        assert_eq!(pixel(&frame, 8, 0), SHADES[0]);
+        assert_eq!(pixel(&frame, 16, 7), SHADES[2]);
+        assert_eq!(pixel(&frame, 16, 0), SHADES[0]);
+        assert_eq!(pixel(&frame, 31, 7), SHADES[2]);
+        // OBP1 instead of OBP0
+        assert_eq!(pixel(&frame, 32, 0), SHADES[1]);
+    }
+
+    #[test]
+    fn background_over_sprites() {
+        // the first tile of the background is color 2, the rest color 0
+        let mut gpu = gpu();
+        gpu.write_vram(0x9800, 3);
+        sprite(&mut gpu, 0, 16, 12, 1, 0x80);
+        // the sprite behind the background still hides the ones below it
+        sprite(&mut gpu, 1, 16, 14, 2, 0);
+
+        let frame = frame(&mut gpu, 0x93);
+        assert_eq!(pixel(&frame, 6, 0), SHADES[2]);
+        assert_eq!(pixel(&frame, 8, 0), SHADES[1]);
+        assert_eq!(pixel(&frame, 11, 0), SHADES[1]);
+        assert_eq!(pixel(&frame, 12, 0), SHADES[3]);
+    }
+}
@@ -0,0 +1,66 @@
+// interrupt sources, in priority order (bit 0 of IE/IF is serviced first)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interrupt {
+    VBlank = 0,
+    LcdStat = 1,
+    Timer = 2,
+    Serial = 3,
+    Joypad = 4,
+}
+
+impl Interrupt {
+    // bit of this interrupt in IE and IF
+    pub fn mask(self) -> u8 {
+        1 << (self as u8)
+    }
+
+    // address the cpu jumps to when servicing this interrupt
+    pub fn vector(self) -> u16 {
+        0x40 + 8 * (self as u16)
+    }
+
+    // highest priority interrupt set in given IE & IF bits
+    pub fn highest(pending: u8) -> Option<Interrupt> {
+        [Interrupt::VBlank, Interrupt::LcdStat, Interrupt::Timer, Interrupt::Serial, Interrupt::Joypad]
+            .iter()
+            .copied()
+            .find(|i| pending & i.mask() != 0)
+    }
+}
+
+pub struct Interrupts {
+    enable: u8,  // IE, 0xFFFF
+    flags: u8,   // IF, 0xFF0F
+}
+
+impl Interrupts {
+    pub fn init() -> Interrupts {
+        Interrupts {
+            enable: 0,
+            flags: 0,
+        }
+    }
+
+    // raise an interrupt request, used by the gpu, timer, serial and joypad
+    pub fn request(&mut self, intr: Interrupt) {
+        self.flags |= intr.mask();
+    }
+
+    // clear an interrupt request once the cpu services it
+    pub fn clear(&mut self, intr: Interrupt) {
+        self.flags &= !intr.mask();
+    }
+
+    // requested interrupts that are also enabled, regardless of IME
+    pub fn pending(&self) -> u8 {
+        self.enable & self.flags & 0x1f
+    }
+
+    // only the lower 5 bits of IF exist, the rest read as 1
+    pub fn read_if(&self) -> u8 { self.flags | 0xe0 }
+    pub fn write_if(&mut self, val: u8) { self.flags = val & 0x1f; }
+
+    // all 8 bits of IE are readable and writable
+    pub fn read_ie(&self) -> u8 { self.enable }
+    pub fn write_ie(&mut self, val: u8) { self.enable = val; }
+}
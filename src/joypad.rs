@@ -0,0 +1,76 @@
+use crate::interrupts::{Interrupt, Interrupts};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Button {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start,
+}
+
+impl Button {
+    // (select bit in P1 of the line the button is on, input bit it pulls low)
+    fn line(self) -> (u8, u8) {
+        match self {
+            Button::Right => (0x10, 0x01),
+            Button::Left => (0x10, 0x02),
+            Button::Up => (0x10, 0x04),
+            Button::Down => (0x10, 0x08),
+            Button::A => (0x20, 0x01),
+            Button::B => (0x20, 0x02),
+            Button::Select => (0x20, 0x04),
+            Button::Start => (0x20, 0x08),
+        }
+    }
+}
+
+// P1 (FF00). bits 4 and 5 select the direction and action buttons, and bits 0-3 read the
+// selected buttons with 0 meaning pressed
+pub struct Joypad {
+    select: u8,     // bits 4 and 5 as written, 0 selects a line
+    directions: u8, // pressed direction buttons, in the bits they show up in
+    actions: u8,    // pressed action buttons
+}
+
+impl Joypad {
+    pub fn init() -> Joypad {
+        Joypad {
+            select: 0x30,
+            directions: 0,
+            actions: 0,
+        }
+    }
+
+    // pressed buttons on the selected lines, active high
+    fn pressed(&self) -> u8 {
+        let mut pressed = 0;
+        if self.select & 0x10 == 0 { pressed |= self.directions; }
+        if self.select & 0x20 == 0 { pressed |= self.actions; }
+        pressed
+    }
+
+    // pressing a button on a selected line pulls an input low, which requests the joypad
+    // interrupt
+    pub fn set_pressed(&mut self, button: Button, pressed: bool, intr: &mut Interrupts) {
+        let before = self.pressed();
+        let (line, bit) = button.line();
+        let buttons = if line == 0x10 { &mut self.directions } else { &mut self.actions };
+        if pressed { *buttons |= bit; } else { *buttons &= !bit; }
+
+        if self.pressed() & !before != 0 {
+            intr.request(Interrupt::Joypad);
+        }
+    }
+
+    pub fn read_reg(&self) -> u8 {
+        0xc0 | self.select | (!self.pressed() & 0x0f)
+    }
+
+    pub fn write_reg(&mut self, val: u8) {
+        self.select = val & 0x30;
+    }
+}
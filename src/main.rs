@@ -1,49 +1,101 @@
 #![allow(dead_code)]
 
 extern crate minifb;
-use minifb::{Key, Window, WindowOptions};
+use minifb::{Key, Window};
 
 mod regs;
+mod bus;
 mod clock;
 mod mmu;
 mod cpu;
 mod gpu;
-
+mod interrupts;
+mod model;
+mod timer;
+mod joypad;
+mod serial;
+mod apu;
+mod cartridge;
+mod mbc;
+mod dma;
+mod opcodes;
+#[cfg(test)]
+mod sst;
+
+use cartridge::Cartridge;
 use cpu::CPU;
-use gpu::GPU;
-use mmu::MMU;
-use std::num::Wrapping;
-
-const WIDTH: usize = 256;
-const HEIGHT: usize = 256;
-// only 160*144 out of 256*256 pixels are displayed controlled by scrollx and scroly
-
-const CYCLES_PER_UPDATE: u32 = 69833;
+use gpu::{SCREEN_WIDTH, SCREEN_HEIGHT};
+use joypad::Button;
+use model::Model;
+use std::cell::Cell;
+use std::path::Path;
+use std::rc::Rc;
+
+// machine cycles the lcd takes to draw a frame, 154 lines of 456 clocks
+const CYCLES_PER_FRAME: u32 = 17556;
+
+// keyboard key for each joypad button
+const KEYS: [(Key, Button); 8] = [
+    (Key::Right, Button::Right),
+    (Key::Left, Button::Left),
+    (Key::Up, Button::Up),
+    (Key::Down, Button::Down),
+    (Key::Z, Button::A),
+    (Key::X, Button::B),
+    (Key::Backspace, Button::Select),
+    (Key::Enter, Button::Start),
+];
 
 fn main() {
-
+    let args: Vec<String> = std::env::args().collect();
 
     //open rom
     let rom_file = "../Roms/tetris.gb";
-    let boot_file = "../Roms/DMR_ROM.bin";
-    //rom::openRom(romName);
-
-    //set up cpu?
-    //let cpu = CPU:init();
-
-
-   //create window
-    let mut frame: Vec<u32> = vec![0; WIDTH * HEIGHT];
 
+    // the model to emulate: rustyboi --model <dmg0|dmg|mgb|sgb|sgb2|cgb|agb>, by default the
+    // most capable one the game makes use of
+    let model = match args.iter().position(|arg| arg == "--model") {
+        Some(i) => match args.get(i + 1).and_then(|name| Model::from_name(name)) {
+            Some(model) => model,
+            None => {
+                println!("--model needs one of dmg0, dmg, mgb, sgb, sgb2, cgb or agb");
+                std::process::exit(1);
+            },
+        },
+        None => std::fs::read(rom_file)
+            .map(|rom| Cartridge::parse(&rom).preferred_model())
+            .unwrap_or(Model::Dmg),     // CPU::init reports why the rom can't be read
+    };
+    let boot_file = if model.is_cgb() { "../Roms/CGB_ROM.bin" } else { "../Roms/DMG_ROM.bin" };
+
+    // the boot rom is optional, without it the cpu starts right at the cartridge entry point
+    let boot_file = if Path::new(boot_file).exists() { Some(boot_file) } else { None };
+    let mut cpu = match CPU::init(rom_file, boot_file, model) {
+        Ok(cpu) => cpu,
+        Err(why) => {
+            println!("couldn't start {}: {}", rom_file, why);
+            std::process::exit(1);
+        },
+    };
+
+    // rumble cartridges turn their motor on and off through the mapper, shown in the title
+    let rumbling = Rc::new(Cell::new(false));
+    let motor = rumbling.clone();
+    cpu.bus_mut().set_rumble_handler(move |on| motor.set(on));
+    let mut title_rumbling = false;
+
+    //create window
+    let title = format!("Rusty Boi ;) - {}", cpu.bus().cartridge().title());
     let mut window = Window::new(
-        "Rusty Boi ;)",
-        WIDTH,
-        HEIGHT,
+        &title,
+        SCREEN_WIDTH,
+        SCREEN_HEIGHT,
         minifb::WindowOptions {
-                resize: true, // TODO allow resize
-                scale: minifb::Scale::X4,
-                ..minifb::WindowOptions::default()
-            }, //WindowOptions::Default
+            resize: true,
+            scale: minifb::Scale::X4,
+            scale_mode: minifb::ScaleMode::AspectRatioStretch,
+            ..minifb::WindowOptions::default()
+        },
     )
     .unwrap_or_else(|e| {
         panic!("{}", e);
@@ -52,32 +104,47 @@ fn main() {
     // Limit to max ~60 fps update rate
     window.limit_update_rate(Some(std::time::Duration::from_micros(16600)));
 
-    // construct cpu, mmu and gpu
-    // let mut mmu = MMU::init(rom_file, boot_file);
-    // let mut gpu = GPU::init(&mut mmu);
-    let mut cpu = CPU::init(rom_file);
-
-    //first run cpu such that total cycles is approximately 1/60 second, then update buffer
-    let mut total_cycles : u32 = 0;
-    let mut cycles_passed: u32 = 0;
+    //run the cpu until the gpu finishes a frame, or for as long as a frame takes when the lcd
+    //is off and nothing gets drawn
     while window.is_open() {
-        //run cpu
-        while cycles_passed < CYCLES_PER_UPDATE {
-            let ticks = cpu.cpu_cycle();
-            cycles_passed += ticks as u32;
-            total_cycles += ticks as u32;
-            // gpu.step(cycles_passed as u32);
+        for (key, button) in KEYS.iter() {
+            cpu.bus_mut().set_button(*button, window.is_key_down(*key));
         }
 
-        cycles_passed -= CYCLES_PER_UPDATE;
-
-        //udpate window buffer with 
-        for x in 0..WIDTH{
-            for y in 0..HEIGHT{
-                // frame[x*HEIGHT + y] = gpu.output()[x*HEIGHT + y];
+        let mut cycles_passed: u32 = 0;
+        while cycles_passed < CYCLES_PER_FRAME && !cpu.bus_mut().gpu_mut().take_frame() {
+            match cpu.step() {
+                Ok(step) => cycles_passed += step.cycles as u32,
+                Err(fault) => {
+                    println!("{}", fault);
+                    save(&mut cpu);
+                    return
+                },
             }
         }
-        
-        window.update_with_buffer(&frame, WIDTH, HEIGHT);
+
+        if let Err(why) = cpu.bus_mut().save_if_idle() {
+            println!("couldn't save: {}", why);
+        }
+
+        if rumbling.get() != title_rumbling {
+            title_rumbling = rumbling.get();
+            window.set_title(&if title_rumbling { format!("{} [rumble]", title) } else { title.clone() });
+        }
+
+        if let Err(why) = window.update_with_buffer(cpu.bus().gpu().frame(), SCREEN_WIDTH, SCREEN_HEIGHT) {
+            println!("couldn't draw the frame: {}", why);
+            save(&mut cpu);
+            return
+        }
+    }
+
+    save(&mut cpu);
+}
+
+// write the battery backed ram of the cartridge before quitting
+fn save(cpu: &mut CPU) {
+    if let Err(why) = cpu.bus_mut().save() {
+        println!("couldn't save: {}", why);
     }
 }
\ No newline at end of file
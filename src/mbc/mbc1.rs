@@ -0,0 +1,186 @@
+use super::{banked_read, banked_write, pad_rom, Mapper, RAM_BANK_SIZE, ROM_BANK_SIZE};
+use crate::cartridge::NINTENDO_LOGO;
+
+// up to 2MB of rom and 32K of ram
+pub struct Mbc1 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool,  // 0000-1FFF, 0x0A in the lower nibble enables
+    bank1: u8,          // 2000-3FFF, lower 5 bits of the rom bank, 0 is treated as 1
+    bank2: u8,          // 4000-5FFF, upper 2 bits of the rom bank or the ram bank
+    mode: bool,         // 6000-7FFF, whether bank2 also applies to 0000-3FFF and ram
+    multicart: bool,    // MBC1M, bank1 only has 4 bits wired so bank2 picks one of 4 games
+}
+
+impl Mbc1 {
+    pub fn init(rom: Vec<u8>, ram_size: usize) -> Mbc1 {
+        let rom = pad_rom(rom);
+        let multicart = Mbc1::is_multicart(&rom);
+        Mbc1 {
+            rom,
+            ram: vec![0; ram_size],
+            ram_enabled: false,
+            bank1: 1,
+            bank2: 0,
+            mode: false,
+            multicart,
+        }
+    }
+
+    // multicarts are all 1MB and have the header of another game, logo included, at the start
+    // of the second 256K
+    fn is_multicart(rom: &[u8]) -> bool {
+        let logo = 0x10 * ROM_BANK_SIZE + 0x104;
+        rom.len() == 0x100000 && rom[logo..logo + NINTENDO_LOGO.len()] == NINTENDO_LOGO
+    }
+
+    // how far bank2 is shifted up in the rom bank number
+    fn bank2_shift(&self) -> u8 {
+        if self.multicart { 4 } else { 5 }
+    }
+
+    fn bank1_mask(&self) -> u8 {
+        if self.multicart { 0x0f } else { 0x1f }
+    }
+
+    fn ram_bank(&self) -> usize {
+        if self.mode { self.bank2 as usize } else { 0 }
+    }
+}
+
+impl Mapper for Mbc1 {
+    fn read_rom(&self, addr: u16) -> u8 {
+        banked_read(&self.rom, ROM_BANK_SIZE, self.rom_bank(addr) as usize, addr)
+    }
+
+    fn write_rom(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x1fff => self.ram_enabled = val & 0x0f == 0x0a,
+            // only a 0 in all 5 bits is turned into 1, so banks 0x20, 0x40 and 0x60 can't be
+            // mapped to 4000-7FFF
+            0x2000..=0x3fff => self.bank1 = if val & 0x1f == 0 { 1 } else { val & 0x1f },
+            0x4000..=0x5fff => self.bank2 = val & 0x03,
+            _ => self.mode = val & 0x01 != 0,
+        }
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return 0xff
+        }
+        banked_read(&self.ram, RAM_BANK_SIZE, self.ram_bank(), addr)
+    }
+
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        if self.ram_enabled {
+            let bank = self.ram_bank();
+            banked_write(&mut self.ram, RAM_BANK_SIZE, bank, addr, val);
+        }
+    }
+
+    fn rom_bank(&self, addr: u16) -> u16 {
+        let upper = (self.bank2 << self.bank2_shift()) as u16;
+        if addr < 0x4000 {
+            if self.mode { upper } else { 0 }
+        } else {
+            upper | (self.bank1 & self.bank1_mask()) as u16
+        }
+    }
+
+    fn ram(&self) -> &[u8] { &self.ram }
+    fn ram_mut(&mut self) -> &mut [u8] { &mut self.ram }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mbc::rom;
+
+    // 1MB with another game's header in the second 256K, as on a multicart
+    fn multicart_rom() -> Vec<u8> {
+        let mut rom = rom(64);
+        let logo = 0x10 * ROM_BANK_SIZE + 0x104;
+        rom[logo..logo + NINTENDO_LOGO.len()].copy_from_slice(&NINTENDO_LOGO);
+        rom
+    }
+
+    #[test]
+    fn bank_0_maps_to_1() {
+        let mut mbc = Mbc1::init(rom(128), 0);
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+
+        // only all 5 bits being 0 counts, so 0x20 can't be mapped and gives 0x21
+        mbc.write_rom(0x4000, 0x01);
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 0x21);
+        mbc.write_rom(0x2000, 0xe5);    // bits past the 5th are ignored
+        assert_eq!(mbc.read_rom(0x4000), 0x25);
+    }
+
+    #[test]
+    fn mode_1_banks_low_rom_and_ram() {
+        let mut mbc = Mbc1::init(rom(128), 0x8000);
+        mbc.write_rom(0x0000, 0x0a);
+        mbc.write_rom(0x4000, 0x02);
+        mbc.write_ram(0xa000, 0x11);
+
+        // in mode 0 bank2 only picks the upper rom bank bits
+        assert_eq!(mbc.read_rom(0x0000), 0);
+        assert_eq!(mbc.read_rom(0x4000), 0x41);
+        assert_eq!(mbc.ram()[0], 0x11);
+
+        // in mode 1 it also banks 0000-3FFF and the ram
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_rom(0x0000), 0x40);
+        assert_eq!(mbc.rom_bank(0x0000), 0x40);
+        assert_eq!(mbc.read_ram(0xa000), 0x00);
+        mbc.write_ram(0xa000, 0x22);
+        assert_eq!(mbc.ram()[2 * RAM_BANK_SIZE], 0x22);
+
+        mbc.write_rom(0x6000, 0x00);
+        assert_eq!(mbc.read_ram(0xa000), 0x11);
+    }
+
+    #[test]
+    fn ram_disabled() {
+        let mut mbc = Mbc1::init(rom(4), 0x2000);
+        mbc.write_ram(0xa000, 0x33);
+        assert_eq!(mbc.read_ram(0xa000), 0xff);
+        mbc.write_rom(0x0000, 0x0a);
+        assert_eq!(mbc.read_ram(0xa000), 0x00);
+        mbc.write_rom(0x0000, 0x00);
+        assert_eq!(mbc.read_ram(0xa000), 0xff);
+    }
+
+    #[test]
+    fn small_rom_wraps() {
+        let mut mbc = Mbc1::init(rom(4), 0);
+        mbc.write_rom(0x2000, 0x06);
+        assert_eq!(mbc.read_rom(0x4000), 2);
+    }
+
+    #[test]
+    fn multicart_aliasing() {
+        let mut mbc = Mbc1::init(multicart_rom(), 0);
+        assert!(mbc.multicart);
+
+        // bank1 only has 4 bits, so bit 4 is dropped and bank2 picks the game
+        mbc.write_rom(0x2000, 0x13);
+        assert_eq!(mbc.read_rom(0x4000), 0x03);
+        mbc.write_rom(0x4000, 0x02);
+        assert_eq!(mbc.read_rom(0x4000), 0x23);
+
+        // bank1 of 0x10 has all 4 wired bits 0 but isn't turned into 1, so it maps the
+        // first bank of the game to 4000-7FFF as well
+        mbc.write_rom(0x2000, 0x10);
+        assert_eq!(mbc.read_rom(0x4000), 0x20);
+
+        // mode 1 maps the first bank of the game to 0000-3FFF, where its header is
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_rom(0x0000), 0x20);
+
+        // a 1MB rom without the second header is a normal mbc1
+        assert!(!Mbc1::init(rom(64), 0).multicart);
+    }
+}
@@ -0,0 +1,124 @@
+use super::{banked_read, pad_rom, Mapper, ROM_BANK_SIZE};
+
+// bytes of built in ram, of which only the lower nibble exists
+const RAM_SIZE: usize = 0x200;
+
+// up to 256K of rom, and 512 4 bit values of ram on the chip itself
+pub struct Mbc2 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    rom_bank: u8,
+}
+
+impl Mbc2 {
+    pub fn init(rom: Vec<u8>) -> Mbc2 {
+        Mbc2 {
+            rom: pad_rom(rom),
+            ram: vec![0; RAM_SIZE],
+            ram_enabled: false,
+            rom_bank: 1,
+        }
+    }
+}
+
+impl Mapper for Mbc2 {
+    fn read_rom(&self, addr: u16) -> u8 {
+        banked_read(&self.rom, ROM_BANK_SIZE, self.rom_bank(addr) as usize, addr)
+    }
+
+    // both registers are in 0000-3FFF, bit 8 of the address picks which one
+    fn write_rom(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x3fff if addr & 0x100 == 0 => self.ram_enabled = val & 0x0f == 0x0a,
+            0x0000..=0x3fff => self.rom_bank = if val & 0x0f == 0 { 1 } else { val & 0x0f },
+            _ => (),
+        }
+    }
+
+    // only 9 address bits are decoded, so the ram repeats all over A000-BFFF
+    fn read_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xff
+        }
+        self.ram[addr as usize % RAM_SIZE] | 0xf0
+    }
+
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        if self.ram_enabled {
+            self.ram[addr as usize % RAM_SIZE] = val & 0x0f;
+        }
+    }
+
+    fn rom_bank(&self, addr: u16) -> u16 {
+        if addr < 0x4000 { 0 } else { self.rom_bank as u16 }
+    }
+
+    fn ram(&self) -> &[u8] { &self.ram }
+    fn ram_mut(&mut self) -> &mut [u8] { &mut self.ram }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        for (byte, saved) in self.ram.iter_mut().zip(data.iter()) {
+            *byte = saved & 0x0f;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mbc::rom;
+
+    #[test]
+    fn registers_picked_by_address_bit_8() {
+        let mut mbc = Mbc2::init(rom(16));
+
+        // bit 8 set selects the rom bank, so this doesn't enable the ram
+        mbc.write_rom(0x2100, 0x0a);
+        assert_eq!(mbc.read_rom(0x4000), 0x0a);
+        assert_eq!(mbc.read_ram(0xa000), 0xff);
+
+        mbc.write_rom(0x3f00, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+        mbc.write_rom(0x0100, 0x13);    // only 4 bits
+        assert_eq!(mbc.read_rom(0x4000), 3);
+
+        mbc.write_rom(0x2000, 0x0a);
+        assert_eq!(mbc.read_ram(0xa000), 0xf0);
+    }
+
+    #[test]
+    fn nibble_ram() {
+        let mut mbc = Mbc2::init(vec![0; 2 * ROM_BANK_SIZE]);
+        mbc.write_rom(0x0000, 0x0a);
+
+        // only the lower nibble is stored, the upper one reads as 1s
+        mbc.write_ram(0xa005, 0xab);
+        assert_eq!(mbc.read_ram(0xa005), 0xfb);
+        assert_eq!(mbc.ram()[5], 0x0b);
+
+        // 512 values repeated over the whole of A000-BFFF
+        assert_eq!(mbc.read_ram(0xa205), 0xfb);
+        assert_eq!(mbc.read_ram(0xbe05), 0xfb);
+        mbc.write_ram(0xbfff, 0x07);
+        assert_eq!(mbc.read_ram(0xa1ff), 0xf7);
+
+        mbc.write_rom(0x0000, 0x00);
+        assert_eq!(mbc.read_ram(0xa005), 0xff);
+        mbc.write_ram(0xa005, 0x01);
+        assert_eq!(mbc.ram()[5], 0x0b);
+    }
+
+    #[test]
+    fn save_data() {
+        let mut mbc = Mbc2::init(vec![0; 2 * ROM_BANK_SIZE]);
+        mbc.write_rom(0x0000, 0x0a);
+        mbc.write_ram(0xa001, 0x0c);
+        assert_eq!(mbc.save_data().len(), RAM_SIZE);
+
+        // saves from emulators that keep the upper nibble set still load
+        let mut loaded = Mbc2::init(vec![0; 2 * ROM_BANK_SIZE]);
+        loaded.load_save_data(&[0xf1, 0xfc]);
+        assert_eq!(loaded.ram()[..2], [0x01, 0x0c]);
+    }
+}
@@ -0,0 +1,170 @@
+use super::rtc::{Rtc, RTC_SAVE_SIZE};
+use super::{banked_read, banked_write, pad_rom, Mapper, RAM_BANK_SIZE, ROM_BANK_SIZE};
+
+// up to 2MB of rom, 32K of ram and optionally a real time clock
+pub struct Mbc3 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    rtc: Option<Rtc>,
+    enabled: bool,      // 0000-1FFF, 0x0A in the lower nibble enables ram and the clock
+    rom_bank: u8,       // 2000-3FFF, 7 bits, 0 is treated as 1
+    select: u8,         // 4000-5FFF, ram bank 0-3 or clock register 0x08-0x0C
+    latch: u8,          // last value written to 6000-7FFF, writing 0 then 1 latches the clock
+}
+
+impl Mbc3 {
+    pub fn init(rom: Vec<u8>, ram_size: usize, has_rtc: bool) -> Mbc3 {
+        Mbc3 {
+            rom: pad_rom(rom),
+            ram: vec![0; ram_size],
+            rtc: if has_rtc { Some(Rtc::init()) } else { None },
+            enabled: false,
+            rom_bank: 1,
+            select: 0,
+            latch: 0xff,
+        }
+    }
+}
+
+impl Mapper for Mbc3 {
+    fn read_rom(&self, addr: u16) -> u8 {
+        banked_read(&self.rom, ROM_BANK_SIZE, self.rom_bank(addr) as usize, addr)
+    }
+
+    fn write_rom(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x1fff => self.enabled = val & 0x0f == 0x0a,
+            0x2000..=0x3fff => self.rom_bank = if val & 0x7f == 0 { 1 } else { val & 0x7f },
+            0x4000..=0x5fff => self.select = val & 0x0f,
+            _ => {
+                if self.latch == 0 && val == 1 {
+                    if let Some(rtc) = &mut self.rtc { rtc.latch(); }
+                }
+                self.latch = val;
+            },
+        }
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        if !self.enabled {
+            return 0xff
+        }
+        match (self.select, &self.rtc) {
+            (0x00..=0x03, _) if !self.ram.is_empty() => banked_read(&self.ram, RAM_BANK_SIZE, self.select as usize, addr),
+            (0x08..=0x0c, Some(rtc)) => rtc.read_reg(self.select),
+            _ => 0xff,
+        }
+    }
+
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        if !self.enabled {
+            return
+        }
+        match (self.select, &mut self.rtc) {
+            (0x00..=0x03, _) => banked_write(&mut self.ram, RAM_BANK_SIZE, self.select as usize, addr, val),
+            (0x08..=0x0c, Some(rtc)) => rtc.write_reg(self.select, val),
+            _ => (),
+        }
+    }
+
+    fn rom_bank(&self, addr: u16) -> u16 {
+        if addr < 0x4000 { 0 } else { self.rom_bank as u16 }
+    }
+
+    fn ram(&self) -> &[u8] { &self.ram }
+    fn ram_mut(&mut self) -> &mut [u8] { &mut self.ram }
+
+    fn tick(&mut self, cycles: u8) {
+        if let Some(rtc) = &mut self.rtc {
+            rtc.tick(cycles);
+        }
+    }
+
+    // the clock is saved after the ram
+    fn save_data(&self) -> Vec<u8> {
+        let mut data = self.ram.clone();
+        if let Some(rtc) = &self.rtc {
+            data.extend(rtc.save());
+        }
+        data
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        let ram_len = self.ram.len().min(data.len());
+        self.ram[..ram_len].copy_from_slice(&data[..ram_len]);
+        if let Some(rtc) = &mut self.rtc {
+            let footer = &data[ram_len..];
+            rtc.load(&footer[..footer.len().min(RTC_SAVE_SIZE)]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mbc::rom;
+
+    #[test]
+    fn rom_banks() {
+        let mut mbc = Mbc3::init(rom(128), 0, false);
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+        mbc.write_rom(0x2000, 0x7f);
+        assert_eq!(mbc.read_rom(0x4000), 0x7f);
+        assert_eq!(mbc.read_rom(0x0000), 0);
+    }
+
+    #[test]
+    fn clock_registers() {
+        let mut mbc = Mbc3::init(vec![0; 2 * ROM_BANK_SIZE], 0x8000, true);
+        mbc.write_rom(0x0000, 0x0a);
+        mbc.write_rom(0x4000, 0x02);
+        mbc.write_ram(0xa000, 0x44);
+        assert_eq!(mbc.read_ram(0xa000), 0x44);
+        assert_eq!(mbc.ram()[2 * RAM_BANK_SIZE], 0x44);
+
+        // writing a clock register is seen right away, counting only after latching
+        mbc.write_rom(0x4000, 0x08);
+        mbc.write_ram(0xa000, 10);
+        assert_eq!(mbc.read_ram(0xa000), 10);
+        for _ in 0..(1 << 20) / 4 {
+            mbc.tick(4);
+        }
+        assert_eq!(mbc.read_ram(0xa000), 10);
+        mbc.write_rom(0x6000, 0x01);    // latching needs a 0 written first
+        assert_eq!(mbc.read_ram(0xa000), 10);
+        mbc.write_rom(0x6000, 0x00);
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_ram(0xa000), 11);
+
+        // no register there
+        mbc.write_rom(0x4000, 0x05);
+        assert_eq!(mbc.read_ram(0xa000), 0xff);
+    }
+
+    #[test]
+    fn save_data_has_clock_footer() {
+        let mut mbc = Mbc3::init(vec![0; 2 * ROM_BANK_SIZE], 0x2000, true);
+        mbc.write_rom(0x0000, 0x0a);
+        mbc.write_ram(0xa010, 0x55);
+        mbc.write_rom(0x4000, 0x0c);
+        mbc.write_ram(0xa000, 0x40);    // halt the clock
+        mbc.write_rom(0x4000, 0x0a);
+        mbc.write_ram(0xa000, 13);
+
+        let data = mbc.save_data();
+        assert_eq!(data.len(), 0x2000 + RTC_SAVE_SIZE);
+        assert_eq!(data[0x10], 0x55);
+        assert_eq!(data[0x2000 + 8], 13);
+
+        let mut loaded = Mbc3::init(vec![0; 2 * ROM_BANK_SIZE], 0x2000, true);
+        loaded.load_save_data(&data);
+        loaded.write_rom(0x0000, 0x0a);
+        assert_eq!(loaded.read_ram(0xa010), 0x55);
+        loaded.write_rom(0x4000, 0x0a);
+        assert_eq!(loaded.read_ram(0xa000), 13);
+
+        // without a clock the save is just the ram
+        assert_eq!(Mbc3::init(vec![0; 2 * ROM_BANK_SIZE], 0x2000, false).save_data().len(), 0x2000);
+    }
+}
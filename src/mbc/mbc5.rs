@@ -0,0 +1,180 @@
+use super::{banked_read, banked_write, pad_rom, Mapper, RAM_BANK_SIZE, ROM_BANK_SIZE};
+
+// up to 8MB of rom and 128K of ram. rumble cartridges wire bit 3 of the ram bank to the motor
+pub struct Mbc5 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool,  // 0000-1FFF, only exactly 0x0A enables
+    rom_bank: u16,      // 2000-2FFF lower 8 bits, 3000-3FFF bit 8. bank 0 can be mapped too
+    ram_bank: u8,       // 4000-5FFF
+    rumble: Option<Rumble>,
+}
+
+// the motor and whoever wants to know about it
+struct Rumble {
+    on: bool,
+    handler: Option<Box<dyn FnMut(bool)>>,
+}
+
+impl Mbc5 {
+    pub fn init(rom: Vec<u8>, ram_size: usize, has_rumble: bool) -> Mbc5 {
+        Mbc5 {
+            rom: pad_rom(rom),
+            ram: vec![0; ram_size],
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            rumble: if has_rumble { Some(Rumble { on: false, handler: None }) } else { None },
+        }
+    }
+
+    fn set_ram_bank(&mut self, val: u8) {
+        match &mut self.rumble {
+            Some(rumble) => {
+                self.ram_bank = val & 0x07;
+                let on = val & 0x08 != 0;
+                if on != rumble.on {
+                    rumble.on = on;
+                    if let Some(handler) = &mut rumble.handler { handler(on); }
+                }
+            },
+            None => self.ram_bank = val & 0x0f,
+        }
+    }
+}
+
+impl Mapper for Mbc5 {
+    fn read_rom(&self, addr: u16) -> u8 {
+        banked_read(&self.rom, ROM_BANK_SIZE, self.rom_bank(addr) as usize, addr)
+    }
+
+    fn write_rom(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x1fff => self.ram_enabled = val == 0x0a,
+            0x2000..=0x2fff => self.rom_bank = (self.rom_bank & 0x100) | val as u16,
+            0x3000..=0x3fff => self.rom_bank = (self.rom_bank & 0xff) | ((val & 0x01) as u16) << 8,
+            0x4000..=0x5fff => self.set_ram_bank(val),
+            _ => (),
+        }
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return 0xff
+        }
+        banked_read(&self.ram, RAM_BANK_SIZE, self.ram_bank as usize, addr)
+    }
+
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        if self.ram_enabled {
+            banked_write(&mut self.ram, RAM_BANK_SIZE, self.ram_bank as usize, addr, val);
+        }
+    }
+
+    fn rom_bank(&self, addr: u16) -> u16 {
+        if addr < 0x4000 { 0 } else { self.rom_bank }
+    }
+
+    fn ram(&self) -> &[u8] { &self.ram }
+    fn ram_mut(&mut self) -> &mut [u8] { &mut self.ram }
+
+    fn set_rumble_handler(&mut self, handler: Box<dyn FnMut(bool)>) {
+        if let Some(rumble) = &mut self.rumble {
+            rumble.handler = Some(handler);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mbc::rom;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // the bank number at 4000, both bytes of it
+    fn bank(mbc: &Mbc5) -> usize {
+        mbc.read_rom(0x4000) as usize | (mbc.read_rom(0x4001) as usize) << 8
+    }
+
+    #[test]
+    fn nine_bit_rom_bank() {
+        let mut mbc = Mbc5::init(rom(512), 0, false);
+        assert_eq!(bank(&mbc), 1);
+        mbc.write_rom(0x2000, 0x23);
+        assert_eq!(bank(&mbc), 0x23);
+        mbc.write_rom(0x3000, 0x01);
+        assert_eq!(bank(&mbc), 0x123);
+        mbc.write_rom(0x2fff, 0x45);   // the low byte leaves bit 8 alone
+        assert_eq!(bank(&mbc), 0x145);
+        mbc.write_rom(0x3fff, 0xfe);   // only bit 0 counts
+        assert_eq!(bank(&mbc), 0x45);
+        assert_eq!(mbc.read_rom(0x0000), 0);
+    }
+
+    #[test]
+    fn bank_0_can_be_mapped() {
+        let mut mbc = Mbc5::init(rom(4), 0, false);
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(bank(&mbc), 0);
+        assert_eq!(mbc.rom_bank(0x4000), 0);
+    }
+
+    #[test]
+    fn ram_enabled_by_exactly_0a() {
+        let mut mbc = Mbc5::init(rom(2), 0x2000, false);
+        for val in [0x1a, 0x0b, 0xfa] {
+            mbc.write_rom(0x0000, val);
+            mbc.write_ram(0xa000, 0x12);
+            assert_eq!(mbc.read_ram(0xa000), 0xff);
+        }
+        mbc.write_rom(0x1fff, 0x0a);
+        mbc.write_ram(0xa000, 0x12);
+        assert_eq!(mbc.read_ram(0xa000), 0x12);
+        mbc.write_rom(0x0000, 0x00);
+        assert_eq!(mbc.read_ram(0xa000), 0xff);
+    }
+
+    #[test]
+    fn ram_bank_bit_3() {
+        // without a motor bit 3 picks one of the upper 8 banks
+        let mut mbc = Mbc5::init(rom(2), 0x20000, false);
+        mbc.write_rom(0x0000, 0x0a);
+        mbc.write_rom(0x4000, 0x09);
+        mbc.write_ram(0xa000, 0x99);
+        mbc.write_rom(0x4000, 0x01);
+        assert_eq!(mbc.read_ram(0xa000), 0x00);
+        assert_eq!(mbc.ram()[9 * RAM_BANK_SIZE], 0x99);
+
+        // with one it drives the motor and isn't part of the bank
+        let mut mbc = Mbc5::init(rom(2), 0x20000, true);
+        mbc.write_rom(0x0000, 0x0a);
+        mbc.write_rom(0x4000, 0x09);
+        mbc.write_ram(0xa000, 0x99);
+        mbc.write_rom(0x4000, 0x01);
+        assert_eq!(mbc.read_ram(0xa000), 0x99);
+        assert_eq!(mbc.ram()[RAM_BANK_SIZE], 0x99);
+    }
+
+    #[test]
+    fn rumble_reports_changes() {
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let mut mbc = Mbc5::init(rom(2), 0x20000, true);
+        let log = calls.clone();
+        mbc.set_rumble_handler(Box::new(move |on| log.borrow_mut().push(on)));
+
+        mbc.write_rom(0x4000, 0x00);
+        mbc.write_rom(0x4000, 0x08);
+        mbc.write_rom(0x4000, 0x09);   // still on, only the bank changes
+        mbc.write_rom(0x4000, 0x01);
+        mbc.write_rom(0x4000, 0x02);
+        assert_eq!(*calls.borrow(), vec![true, false]);
+
+        // nothing to report without a motor
+        let mut mbc = Mbc5::init(rom(2), 0x20000, false);
+        let log = calls.clone();
+        mbc.set_rumble_handler(Box::new(move |on| log.borrow_mut().push(on)));
+        mbc.write_rom(0x4000, 0x08);
+        assert_eq!(calls.borrow().len(), 2);
+    }
+}
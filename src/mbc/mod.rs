@@ -0,0 +1,108 @@
+// memory bank controllers, the chips on the cartridge that map its rom and ram into 0000-7FFF
+// and A000-BFFF and are controlled by writing to the rom area
+
+mod rom_only;
+mod mbc1;
+mod mbc2;
+mod mbc3;
+mod mbc5;
+mod rtc;
+
+pub use self::rom_only::RomOnly;
+pub use self::mbc1::Mbc1;
+pub use self::mbc2::Mbc2;
+pub use self::mbc3::Mbc3;
+pub use self::mbc5::Mbc5;
+
+use crate::cartridge::{Cartridge, MapperKind};
+
+pub const ROM_BANK_SIZE: usize = 0x4000;
+pub const RAM_BANK_SIZE: usize = 0x2000;
+
+pub trait Mapper {
+    // 0000-7FFF
+    fn read_rom(&self, addr: u16) -> u8;
+    // writes to 0000-7FFF set the mapper's registers
+    fn write_rom(&mut self, addr: u16, val: u8);
+
+    // A000-BFFF, reads 0xFF while the ram is disabled or missing
+    fn read_ram(&self, addr: u16) -> u8;
+    fn write_ram(&mut self, addr: u16, val: u8);
+
+    // rom bank mapped at the given address in 0000-7FFF
+    fn rom_bank(&self, addr: u16) -> u16;
+
+    // all of the cartridge ram, regardless of banking
+    fn ram(&self) -> &[u8];
+    fn ram_mut(&mut self) -> &mut [u8];
+
+    // advance anything on the cartridge that runs by itself, like a clock
+    fn tick(&mut self, _cycles: u8) {}
+
+    // what a battery keeps when the gameboy is off, to be written to a save file
+    fn save_data(&self) -> Vec<u8> {
+        self.ram().to_vec()
+    }
+
+    // called with whether the motor is on whenever a rumble cartridge turns it on or off
+    fn set_rumble_handler(&mut self, _handler: Box<dyn FnMut(bool)>) {}
+
+    // restore what save_data returned, possibly from another emulator
+    fn load_save_data(&mut self, data: &[u8]) {
+        let ram = self.ram_mut();
+        let len = ram.len().min(data.len());
+        ram[..len].copy_from_slice(&data[..len]);
+    }
+}
+
+// the mapper of the given cartridge holding its rom, None if it isn't supported
+pub fn from_cartridge(cart: &Cartridge, rom: Vec<u8>) -> Option<Box<dyn Mapper>> {
+    let ram_size = cart.ram_size();
+    match cart.cart_type().mapper {
+        MapperKind::RomOnly => Some(Box::new(RomOnly::init(rom, ram_size))),
+        MapperKind::Mbc1 => Some(Box::new(Mbc1::init(rom, ram_size))),
+        MapperKind::Mbc2 => Some(Box::new(Mbc2::init(rom))),
+        MapperKind::Mbc3 => Some(Box::new(Mbc3::init(rom, ram_size, cart.cart_type().timer))),
+        MapperKind::Mbc5 => Some(Box::new(Mbc5::init(rom, ram_size, cart.cart_type().rumble))),
+        _ => None,
+    }
+}
+
+// the rom padded to a whole number of banks, at least the 2 that are always mapped
+fn pad_rom(mut rom: Vec<u8>) -> Vec<u8> {
+    let banks = rom.len().div_ceil(ROM_BANK_SIZE).max(2);
+    rom.resize(banks * ROM_BANK_SIZE, 0xff);
+    rom
+}
+
+// a rom with its bank number in the first 2 bytes of each bank, low byte first
+#[cfg(test)]
+pub(crate) fn rom(banks: usize) -> Vec<u8> {
+    let mut rom = vec![0; banks * ROM_BANK_SIZE];
+    for bank in 0..banks {
+        rom[bank * ROM_BANK_SIZE] = bank as u8;
+        rom[bank * ROM_BANK_SIZE + 1] = (bank >> 8) as u8;
+    }
+    rom
+}
+
+// byte at addr within the given rom bank, bank numbers past the end of the rom wrap around
+// since the upper bank bits aren't connected
+fn banked_read(data: &[u8], bank_size: usize, bank: usize, addr: u16) -> u8 {
+    if data.is_empty() {
+        return 0xff
+    }
+    let banks = data.len() / bank_size;
+    let bank = if banks > 0 { bank % banks } else { 0 };
+    data[(bank * bank_size + (addr as usize % bank_size)) % data.len()]
+}
+
+fn banked_write(data: &mut [u8], bank_size: usize, bank: usize, addr: u16, val: u8) {
+    if data.is_empty() {
+        return
+    }
+    let banks = data.len() / bank_size;
+    let bank = if banks > 0 { bank % banks } else { 0 };
+    let len = data.len();
+    data[(bank * bank_size + (addr as usize % bank_size)) % len] = val;
+}
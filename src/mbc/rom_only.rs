@@ -0,0 +1,39 @@
+use super::{banked_read, banked_write, pad_rom, Mapper, RAM_BANK_SIZE, ROM_BANK_SIZE};
+
+// 32K of rom and optionally up to 8K of ram with no banking at all
+pub struct RomOnly {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+}
+
+impl RomOnly {
+    pub fn init(rom: Vec<u8>, ram_size: usize) -> RomOnly {
+        RomOnly {
+            rom: pad_rom(rom),
+            ram: vec![0; ram_size.min(RAM_BANK_SIZE)],
+        }
+    }
+}
+
+impl Mapper for RomOnly {
+    fn read_rom(&self, addr: u16) -> u8 {
+        banked_read(&self.rom, ROM_BANK_SIZE, (addr as usize) / ROM_BANK_SIZE, addr)
+    }
+
+    fn write_rom(&mut self, _addr: u16, _val: u8) {}
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        banked_read(&self.ram, RAM_BANK_SIZE, 0, addr)
+    }
+
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        banked_write(&mut self.ram, RAM_BANK_SIZE, 0, addr, val);
+    }
+
+    fn rom_bank(&self, addr: u16) -> u16 {
+        addr / ROM_BANK_SIZE as u16
+    }
+
+    fn ram(&self) -> &[u8] { &self.ram }
+    fn ram_mut(&mut self) -> &mut [u8] { &mut self.ram }
+}
@@ -0,0 +1,254 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// machine cycles per second
+const CYCLES_PER_SECOND: u32 = 1 << 20;
+
+// bytes appended to the save ram for the clock, in the format most emulators share: the 5 live
+// registers and the 5 latched ones as 32 bit words, then the unix time the save was made as a
+// 64 bit word, all little endian
+pub const RTC_SAVE_SIZE: usize = 48;
+
+// real time clock of mbc3 cartridges. it keeps counting with the gameboy off thanks to the
+// battery, so when loading a save it catches up with the time that passed since
+pub struct Rtc {
+    regs: [u8; 5],      // seconds, minutes, hours, lower 8 bits of the day, DH
+    latched: [u8; 5],   // copy of regs the game reads, updated by latching
+    cycles: u32,        // machine cycles into the current second
+}
+
+// bits of DH, the upper day register
+const DAY_HIGH: u8 = 0x01;
+const HALT: u8 = 0x40;
+const DAY_CARRY: u8 = 0x80;
+
+// bits that exist in each register
+const MASKS: [u8; 5] = [0x3f, 0x3f, 0x1f, 0xff, DAY_HIGH | HALT | DAY_CARRY];
+
+impl Rtc {
+    pub fn init() -> Rtc {
+        Rtc {
+            regs: [0; 5],
+            latched: [0; 5],
+            cycles: 0,
+        }
+    }
+
+    fn halted(&self) -> bool {
+        self.regs[4] & HALT != 0
+    }
+
+    fn days(&self) -> u16 {
+        (((self.regs[4] & DAY_HIGH) as u16) << 8) | self.regs[3] as u16
+    }
+
+    // the day counter is 9 bits, overflowing sets the carry bit until the game clears it
+    fn set_days(&mut self, days: u32) {
+        if days > 0x1ff {
+            self.regs[4] |= DAY_CARRY;
+        }
+        let days = days & 0x1ff;
+        self.regs[3] = days as u8;
+        self.regs[4] = (self.regs[4] & !DAY_HIGH) | (days >> 8) as u8;
+    }
+
+    // each counter only wraps when it reaches its limit exactly, so a game that writes an
+    // out of range value sees it count up to the largest value its bits can hold and wrap to 0
+    // without carrying
+    fn advance_second(&mut self) {
+        let limits = [60, 60, 24];
+        for (i, limit) in limits.iter().enumerate() {
+            self.regs[i] = (self.regs[i] + 1) & MASKS[i];
+            if self.regs[i] == *limit {
+                self.regs[i] = 0;
+            } else {
+                return
+            }
+        }
+        self.set_days(self.days() as u32 + 1);
+    }
+
+    fn advance(&mut self, mut seconds: u64) {
+        if self.halted() {
+            return
+        }
+
+        // skip whole days at once, a save can be years old
+        while seconds >= 86400 {
+            self.set_days(self.days() as u32 + (seconds / 86400).min(0x200) as u32);
+            seconds -= (seconds / 86400).min(0x200) * 86400;
+        }
+        for _ in 0..seconds {
+            self.advance_second();
+        }
+    }
+
+    pub fn tick(&mut self, cycles: u8) {
+        if self.halted() {
+            return
+        }
+        self.cycles += cycles as u32;
+        if self.cycles >= CYCLES_PER_SECOND {
+            self.cycles -= CYCLES_PER_SECOND;
+            self.advance_second();
+        }
+    }
+
+    // copy the live registers into the ones the game reads
+    pub fn latch(&mut self) {
+        self.latched = self.regs;
+    }
+
+    // register 0x08-0x0C as selected through 4000-5FFF
+    pub fn read_reg(&self, reg: u8) -> u8 {
+        self.latched[(reg - 0x08) as usize]
+    }
+
+    pub fn write_reg(&mut self, reg: u8, val: u8) {
+        let i = (reg - 0x08) as usize;
+        self.regs[i] = val & MASKS[i];
+        self.latched[i] = self.regs[i];
+        // writing the seconds restarts the current second
+        if i == 0 {
+            self.cycles = 0;
+        }
+    }
+
+    pub fn save(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(RTC_SAVE_SIZE);
+        for reg in self.regs.iter().chain(self.latched.iter()) {
+            data.extend_from_slice(&(*reg as u32).to_le_bytes());
+        }
+        data.extend_from_slice(&unix_time().to_le_bytes());
+        data
+    }
+
+    // restore the clock from a save and catch up with the time since it was made. older saves
+    // only have a 32 bit timestamp
+    pub fn load(&mut self, data: &[u8]) {
+        if data.len() < 44 {
+            return
+        }
+        let word = |i: usize| u32::from_le_bytes([data[i * 4], data[i * 4 + 1], data[i * 4 + 2], data[i * 4 + 3]]);
+        let regs = self.regs.iter_mut().zip(self.latched.iter_mut()).zip(MASKS.iter());
+        for (i, ((reg, latched), mask)) in regs.enumerate() {
+            *reg = word(i) as u8 & mask;
+            *latched = word(i + 5) as u8 & mask;
+        }
+
+        let mut timestamp = word(10) as u64;
+        if data.len() >= RTC_SAVE_SIZE {
+            timestamp |= (word(11) as u64) << 32;
+        }
+        self.advance(unix_time().saturating_sub(timestamp));
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a save footer of the given registers made the given number of seconds ago
+    fn footer(regs: [u8; 5], age: u64) -> Vec<u8> {
+        let mut data = Vec::new();
+        for reg in regs.iter().chain(regs.iter()) {
+            data.extend_from_slice(&(*reg as u32).to_le_bytes());
+        }
+        data.extend_from_slice(&(unix_time() - age).to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn rollover() {
+        let mut rtc = Rtc::init();
+        rtc.regs = [59, 59, 23, 0xff, 0];
+        rtc.advance_second();
+        assert_eq!(rtc.regs, [0, 0, 0, 0x00, DAY_HIGH]);
+
+        // the 9 bit day counter wraps and sets the carry, which stays until cleared
+        rtc.regs = [59, 59, 23, 0xff, DAY_HIGH];
+        rtc.advance_second();
+        assert_eq!(rtc.regs, [0, 0, 0, 0x00, DAY_CARRY]);
+        rtc.advance_second();
+        assert_eq!(rtc.regs[4], DAY_CARRY);
+    }
+
+    #[test]
+    fn out_of_range_values_wrap_without_carry() {
+        let mut rtc = Rtc::init();
+        rtc.write_reg(0x08, 0xff);
+        assert_eq!(rtc.regs[0], 0x3f);
+        rtc.advance_second();
+        assert_eq!(rtc.regs[0], 0);
+        assert_eq!(rtc.regs[1], 0);
+    }
+
+    #[test]
+    fn tick_and_halt() {
+        let mut rtc = Rtc::init();
+        for _ in 0..CYCLES_PER_SECOND / 4 {
+            rtc.tick(4);
+        }
+        assert_eq!(rtc.regs[0], 1);
+
+        rtc.write_reg(0x0c, HALT);
+        for _ in 0..CYCLES_PER_SECOND / 4 {
+            rtc.tick(4);
+        }
+        rtc.advance(3600);
+        assert_eq!(rtc.regs[0], 1);
+        assert_eq!(rtc.regs[2], 0);
+    }
+
+    #[test]
+    fn latch() {
+        let mut rtc = Rtc::init();
+        rtc.regs[1] = 30;
+        assert_eq!(rtc.read_reg(0x09), 0);
+        rtc.latch();
+        rtc.regs[1] = 31;
+        assert_eq!(rtc.read_reg(0x09), 30);
+    }
+
+    #[test]
+    fn save_footer() {
+        let mut rtc = Rtc::init();
+        rtc.regs = [1, 2, 3, 4, HALT | DAY_HIGH];
+        rtc.latched = [5, 6, 7, 8, 0];
+        let data = rtc.save();
+        assert_eq!(data.len(), RTC_SAVE_SIZE);
+        assert_eq!(data[0..4], [1, 0, 0, 0]);
+        assert_eq!(data[16..20], [HALT | DAY_HIGH, 0, 0, 0]);
+        assert_eq!(data[20..24], [5, 0, 0, 0]);
+
+        // halted, so no time passes while loading it back
+        let mut loaded = Rtc::init();
+        loaded.load(&data);
+        assert_eq!(loaded.regs, rtc.regs);
+        assert_eq!(loaded.latched, rtc.latched);
+    }
+
+    #[test]
+    fn load_catches_up() {
+        // 1 day, 1 hour, 1 minute and 1 second ago
+        let mut rtc = Rtc::init();
+        rtc.load(&footer([0, 0, 0, 0, 0], 90061));
+        assert_eq!(rtc.days(), 1);
+        assert_eq!(rtc.regs[2], 1);
+        assert_eq!(rtc.regs[1], 1);
+        assert!(rtc.regs[0] == 1 || rtc.regs[0] == 2);
+
+        // older saves have a 32 bit timestamp
+        let mut rtc = Rtc::init();
+        rtc.load(&footer([0, 0, 5, 0, 0], 0)[..44]);
+        assert_eq!(rtc.regs[2], 5);
+
+        // footers that are too short are ignored
+        let mut rtc = Rtc::init();
+        rtc.load(&footer([0, 0, 5, 0, 0], 0)[..40]);
+        assert_eq!(rtc.regs[2], 0);
+    }
+}
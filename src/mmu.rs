@@ -1,10 +1,72 @@
 use std::error::Error;
+use std::fmt;
 use std::result::Result;
-use std::fs::File;
-use std::io::prelude::*;
-use std::path::Path;
+use std::fs::{self, File};
+use std::io::{self, prelude::*};
+use std::path::{Path, PathBuf};
+use crate::bus::Bus;
+use crate::clock::Clock;
+use crate::interrupts::{Interrupt, Interrupts};
+use crate::model::Model;
+use crate::cartridge::{Cartridge, MapperKind};
+use crate::mbc::{self, Mapper, RomOnly};
+use crate::gpu::GPU;
+use crate::timer::Timer;
+use crate::joypad::{Button, Joypad};
+use crate::serial::Serial;
+use crate::apu::Apu;
+use crate::dma::Dma;
 
 
+// why a rom, boot rom or save file couldn't be loaded
+#[derive(Debug)]
+pub enum LoadError {
+    MissingFile(PathBuf),
+    Io(PathBuf, io::Error),
+    TooSmall(usize),                                // bytes, too short to hold a header
+    UnsupportedMapper(MapperKind),
+    BadHeaderChecksum { expected: u8, actual: u8 },  // the boot rom would refuse to start it
+    BootRomSize { expected: usize, actual: usize },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::MissingFile(path) => write!(f, "{} doesn't exist", path.display()),
+            LoadError::Io(path, why) => write!(f, "couldn't read {}: {}", path.display(), why),
+            LoadError::TooSmall(len) => write!(f, "the rom is only {} bytes, too small to be a gameboy rom", len),
+            LoadError::UnsupportedMapper(MapperKind::Unknown(code)) => write!(f, "unknown cartridge type {:#04x}", code),
+            LoadError::UnsupportedMapper(mapper) => write!(f, "{:?} cartridges aren't supported yet", mapper),
+            LoadError::BadHeaderChecksum { expected, actual } =>
+                write!(f, "the rom header is corrupt, its checksum is {:#04x} but should be {:#04x}", actual, expected),
+            LoadError::BootRomSize { expected, actual } =>
+                write!(f, "the boot rom is {} bytes but should be {} for this model", actual, expected),
+        }
+    }
+}
+
+impl Error for LoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LoadError::Io(_, why) => Some(why),
+            _ => None,
+        }
+    }
+}
+
+// read a whole file, telling a missing file apart from other problems
+fn read_file(path: &Path) -> Result<Vec<u8>, LoadError> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(ref why) if why.kind() == io::ErrorKind::NotFound => return Err(LoadError::MissingFile(path.to_path_buf())),
+        Err(why) => return Err(LoadError::Io(path.to_path_buf(), why)),
+    };
+
+    let mut data = Vec::new();
+    file.read_to_end(&mut data).map_err(|why| LoadError::Io(path.to_path_buf(), why))?;
+    Ok(data)
+}
+
 pub struct MMU {
     // various MMU components: WRAM, VRAM, etc.
     //0000 - 3FFF From cartridge, usually a fixed bank
@@ -20,157 +82,529 @@ pub struct MMU {
     //FF80 - FFFE High RAM (HRAM)	
     //FFFF - FFFF Interrupts Enable Register (IE)	
 
-    memory: [u8; 0x10000],
-    cart: [u8; 0x4000], //cartriage 
+    wram: [u8; 0x2000],
+    hram: [u8; 0x7f],
+    cartridge: Cartridge,   // header of the loaded rom
+    mapper: Box<dyn Mapper>,    // cartridge rom and ram, 0000-7FFF and A000-BFFF
+    save_file: Option<PathBuf>, // where battery backed ram is kept, None without a battery
+    save_dirty: bool,           // cartridge ram was written since it was last saved
+    save_idle: u32,             // machine cycles since the last write to cartridge ram
+    save_age: u32,              // machine cycles since the first unsaved write
+    intr: Interrupts,   // IF (FF0F) and IE (FFFF)
+    clock: Clock,
+    gpu: GPU,           // vram, OAM and FF40-FF4B
+    timer: Timer,       // FF04-FF07
+    joypad: Joypad,     // FF00
+    serial: Serial,     // FF01-FF02
+    apu: Apu,           // FF10-FF3F
+    dma: Dma,           // FF46
+    boot_rom: Vec<u8>,  // overlaid on 0000-00FF (and 0200-08FF on cgb) until FF50 is written
+    boot_mapped: bool,
+    model: Model,
 }
 
+// registers set up by the boot rom, as (address, dmg value, cgb value). sound has to be turned
+// on before its other registers can be written
+const POST_BOOT_IO: [(u16, u8, u8); 34] = [
+    (0xff26, 0x80, 0x80),   // NR52
+    (0xff00, 0xcf, 0xcf),   // P1
+    (0xff01, 0x00, 0x00),   // SB
+    (0xff02, 0x7e, 0x7f),   // SC
+    (0xff05, 0x00, 0x00),   // TIMA
+    (0xff06, 0x00, 0x00),   // TMA
+    (0xff07, 0xf8, 0xf8),   // TAC
+    (0xff10, 0x80, 0x80),   // NR10
+    (0xff11, 0xbf, 0xbf),   // NR11
+    (0xff12, 0xf3, 0xf3),   // NR12
+    (0xff13, 0xff, 0xff),   // NR13
+    (0xff14, 0xbf, 0xbf),   // NR14
+    (0xff16, 0x3f, 0x3f),   // NR21
+    (0xff17, 0x00, 0x00),   // NR22
+    (0xff18, 0xff, 0xff),   // NR23
+    (0xff19, 0xbf, 0xbf),   // NR24
+    (0xff1a, 0x7f, 0x7f),   // NR30
+    (0xff1b, 0xff, 0xff),   // NR31
+    (0xff1c, 0x9f, 0x9f),   // NR32
+    (0xff1d, 0xff, 0xff),   // NR33
+    (0xff1e, 0xbf, 0xbf),   // NR34
+    (0xff20, 0xff, 0xff),   // NR41
+    (0xff21, 0x00, 0x00),   // NR42
+    (0xff22, 0x00, 0x00),   // NR43
+    (0xff23, 0xbf, 0xbf),   // NR44
+    (0xff24, 0x77, 0x77),   // NR50
+    (0xff25, 0xf3, 0xf3),   // NR51
+    (0xff40, 0x91, 0x91),   // LCDC
+    (0xff42, 0x00, 0x00),   // SCY
+    (0xff43, 0x00, 0x00),   // SCX
+    (0xff45, 0x00, 0x00),   // LYC
+    (0xff47, 0xfc, 0xfc),   // BGP
+    (0xff4a, 0x00, 0x00),   // WY
+    (0xff4b, 0x00, 0x00),   // WX
+];
+
+// save once the game stopped writing to cartridge ram for a second, or at least every 10 seconds
+// while it keeps writing
+const SAVE_IDLE_CYCLES: u32 = 1 << 20;
+const SAVE_MAX_AGE_CYCLES: u32 = 10 << 20;
+
+// the ® drawn next to the logo, stored in the boot rom itself
+const REGISTERED_TILE: [u8; 8] = [0x3c, 0x42, 0xb9, 0xa5, 0xb9, 0xa5, 0x42, 0x3c];
+
 impl MMU {
-    pub fn init(rom_file: &str, boot_file: &str) -> MMU {
+    // without a boot rom the memory is set up as the boot rom of the given model would leave it
+    pub fn init(rom_file: &str, boot_file: Option<&str>, model: Model) -> Result<MMU, LoadError> {
         let mut mmu = MMU {
-            memory: [0; 0x10000],
-            cart: [0; 0x4000],        
+            wram: [0; 0x2000],
+            hram: [0; 0x7f],
+            cartridge: Cartridge::parse(&[]),
+            mapper: Box::new(RomOnly::init(Vec::new(), 0)),
+            save_file: None,
+            save_dirty: false,
+            save_idle: 0,
+            save_age: 0,
+            intr: Interrupts::init(),
+            clock: Clock::init(),
+            gpu: GPU::init(),
+            timer: Timer::init(),
+            joypad: Joypad::init(),
+            serial: Serial::init(),
+            apu: Apu::init(),
+            dma: Dma::init(),
+            boot_rom: Vec::new(),
+            boot_mapped: false,
+            model,
         };
         
-        mmu.open_rom(rom_file, boot_file);
-        mmu.cart_init();
-        mmu
-    }
-
-    // internal information in each cart from 0100-014F
-    pub fn cart_init(&mut self){
-        //Scrolling Nintendo graphic
-        self.cart[0x104] = 0xCE;
-        self.cart[0x105] = 0xED;
-        self.cart[0x106] = 0x66;
-        self.cart[0x107] = 0x66;
-        self.cart[0x108] = 0xCC;
-        self.cart[0x109] = 0x0D;
-        self.cart[0x10A] = 0x00;
-        self.cart[0x10B] = 0x0B;
-        self.cart[0x10C] = 0x03;
-        self.cart[0x10D] = 0x73;
-        self.cart[0x10E] = 0x00;
-        self.cart[0x10F] = 0x83;
-        self.cart[0x110] = 0x00;
-        self.cart[0x111] = 0x0C;
-        self.cart[0x112] = 0x00;
-        self.cart[0x113] = 0x0D;
-        self.cart[0x114] = 0x00;
-        self.cart[0x115] = 0x08;
-        self.cart[0x116] = 0x11;
-        self.cart[0x117] = 0x1F;
-        self.cart[0x118] = 0x88;
-        self.cart[0x119] = 0x89;
-        self.cart[0x11A] = 0x00;
-        self.cart[0x11B] = 0x0E;
-        self.cart[0x11C] = 0xDC;
-        self.cart[0x11D] = 0xCC;
-        self.cart[0x11E] = 0x6E;
-        self.cart[0x11F] = 0xE6;
-        self.cart[0x120] = 0xDD;
-        self.cart[0x121] = 0xDD;
-        self.cart[0x122] = 0xD9;
-        self.cart[0x123] = 0x99;
-        self.cart[0x124] = 0xBB;
-        self.cart[0x125] = 0xBB;
-        self.cart[0x126] = 0x67;
-        self.cart[0x127] = 0x63;
-        self.cart[0x128] = 0x6E;
-        self.cart[0x129] = 0x0E;
-        self.cart[0x12A] = 0xEC;
-        self.cart[0x12B] = 0xCC;
-        self.cart[0x12C] = 0xDD;
-        self.cart[0x12D] = 0xDC;
-        self.cart[0x12E] = 0x99;
-        self.cart[0x12F] = 0x9F;
-        self.cart[0x130] = 0xBB;
-        self.cart[0x131] = 0xB9;
-        self.cart[0x132] = 0x33;
-        self.cart[0x133] = 0x3E;
-        
-        self.cart[0x143] = 0x80; //color GB
-        self.cart[0x146] = 0; // gb, not super gb
-        self.cart[0x147] = 0; // using ROM-only cartridge for now
-        self.cart[0x148] = 0; // using 32kb/two banks for now
-        self.cart[0x149] = 0; // not using RAM in cartridge
-        self.cart[0x14A] = 1; // we are not japanese lol
-        self.cart[0x14D] = 1; // document does not make this clear, not sure
+        mmu.open_rom(rom_file)?;
+        match boot_file {
+            Some(boot_file) => mmu.open_boot_rom(boot_file)?,
+            None => mmu.skip_boot(),
+        }
+        Ok(mmu)
+    }
 
+    pub fn model(&self) -> Model {
+        self.model
     }
 
+    // put the io registers, vram and timers in the state the boot rom leaves them in
+    pub fn skip_boot(&mut self) {
+        let cgb = self.model.is_cgb();
+        for (addr, dmg_val, cgb_val) in POST_BOOT_IO.iter() {
+            let mut val = if cgb { *cgb_val } else { *dmg_val };
+            // the sgb boot rom doesn't play the chime, so channel 1 is never triggered
+            if *addr == 0xff14 && self.model.is_sgb() {
+                val &= 0x7f;
+            }
+            self.write_byte(*addr, val);
+        }
 
-    pub fn read_byte(&mut self, addr: u16) -> u8 {
-        self.memory[addr as usize]
+        // the boot rom leaves the timers and lcd at whatever point it finished at, which
+        // depends on the model
+        let (div, stat, ly) = match self.model {
+            Model::Dmg0 => (0x1830, 0x81, 0x91),
+            Model::Dmg | Model::Mgb => (0xabcc, 0x85, 0x00),
+            Model::Sgb | Model::Sgb2 => (0xd800, 0x85, 0x00),
+            Model::Cgb | Model::Agb => (0x1e00, 0x85, 0x00),
+        };
+        self.timer.set_counter(div);
+        self.gpu.set_line(ly, stat & 0x03);
+        self.intr.write_if(0xe1);
+        self.intr.write_ie(0x00);
+
+        self.load_logo();
     }
 
-    pub fn write_byte(&mut self, addr: u16, data: u8) {
-        self.memory[addr as usize] = data;
+    // the boot rom unpacks the nintendo logo from the cartridge header into tiles 1-24, each
+    // pixel doubled in both directions, followed by the ® as tile 25
+    fn load_logo(&mut self) {
+        let mut addr: u16 = 0x8010;
+        for i in 0..48 {
+            let byte = self.mapper.read_rom(0x104 + i);
+            for nibble in [byte >> 4, byte & 0x0f].iter() {
+                let mut row = 0u8;
+                for bit in (0..4).rev() {
+                    row = (row << 2) | if nibble & (1 << bit) != 0 { 0b11 } else { 0 };
+                }
+                // only the low bitplane is used, so every row is written to every other byte
+                self.write_byte(addr, row);
+                self.write_byte(addr + 2, row);
+                addr += 4;
+            }
+        }
+        for row in REGISTERED_TILE.iter() {
+            self.write_byte(addr, *row);
+            addr += 2;
+        }
 
-        // echoing E000-FE00 with C000-DE00--see section 2.5.2
-        if (0xE000 <= addr) & (addr <= 0xFE00){
-            self.memory[addr as usize] = data;
-            let mirror = addr - 0x2000;
-            self.memory[mirror as usize] = data;
+        // the color boot rom clears the tile map again before handing over
+        if self.model.is_cgb() {
+            return
         }
-        if (0xC000 <= addr) & (addr <= 0xE000){
-            self.memory[addr as usize] = data;
-            let mirror = addr + 0x2000;
-            self.memory[mirror as usize] = data;
+
+        // two rows of 12 tiles in the middle of the background, ® at the end of the top one
+        self.write_byte(0x9910, 0x19);
+        for i in 0..12 {
+            self.write_byte(0x9904 + i, 0x01 + i as u8);
+            self.write_byte(0x9924 + i, 0x0d + i as u8);
         }
     }
 
-    pub fn read_word(&mut self, addr: u16) -> u16 {
-        ((self.memory[(addr + 1) as usize] as u16) << 8) | (self.memory[addr as usize] as u16)      // little endian
+    pub fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
     }
 
-    pub fn write_word(&mut self, addr: u16, data: u16) {
-        self.memory[addr as usize] = (data & 0x00ff) as u8;        // little endian
-        self.memory[(addr + 1) as usize] = (data >> 8) as u8;
+    // get told whenever a rumble cartridge turns its motor on or off
+    pub fn set_rumble_handler(&mut self, handler: impl FnMut(bool) + 'static) {
+        self.mapper.set_rumble_handler(Box::new(handler));
+    }
 
-        // echoing E000-FE00 with C000-DE00--see section 2.5.2
-        if (0xE000 <= addr) & (addr <= 0xFE00){
-            self.memory[addr as usize] = (data & 0x00ff) as u8;
-            self.memory[(addr + 1) as usize] = (data >> 8) as u8;
-            let mirror = addr - 0x2000;
-            self.memory[mirror as usize] = (data & 0x00ff) as u8;
-            self.memory[(mirror + 1) as usize] = (data >> 8) as u8;
+    pub fn gpu(&self) -> &GPU {
+        &self.gpu
+    }
+
+    pub fn gpu_mut(&mut self) -> &mut GPU {
+        &mut self.gpu
+    }
+
+    // press or release a button on the joypad
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        self.joypad.set_pressed(button, pressed, &mut self.intr);
+    }
+
+    // raise an interrupt request on behalf of another component
+    pub fn request_interrupt(&mut self, intr: Interrupt) {
+        self.intr.request(intr);
+    }
+
+    pub fn open_rom(&mut self, name: &str) -> Result<(), LoadError> {
+        let path = Path::new(name);
+        let rom_data = read_file(path)?;
+        if rom_data.len() < 0x150 {
+            return Err(LoadError::TooSmall(rom_data.len()))
+        }
+
+        let cartridge = Cartridge::parse(&rom_data);
+        if !cartridge.header_checksum_valid() {
+            let expected = Cartridge::compute_header_checksum(&rom_data);
+            return Err(LoadError::BadHeaderChecksum { expected, actual: cartridge.header_checksum() })
         }
-        if (0xC000 <= addr) & (addr <= 0xE000){
-            self.memory[addr as usize] = (data & 0x00ff) as u8;
-            self.memory[(addr + 1) as usize] = (data >> 8) as u8;
-            let mirror = addr + 0x2000;
-            self.memory[mirror as usize] = (data & 0x00ff) as u8;
-            self.memory[(mirror + 1) as usize] = (data >> 8) as u8;
+        self.mapper = match mbc::from_cartridge(&cartridge, rom_data) {
+            Some(mapper) => mapper,
+            None => return Err(LoadError::UnsupportedMapper(cartridge.cart_type().mapper)),
+        };
+        self.cartridge = cartridge;
+
+        // battery backed ram is kept next to the rom, e.g. tetris.gb and tetris.sav
+        if self.cartridge.cart_type().battery {
+            let save_file = path.with_extension("sav");
+            match read_file(&save_file) {
+                Ok(data) => self.mapper.load_save_data(&data),
+                Err(LoadError::MissingFile(_)) => (),
+                Err(why) => return Err(why),
+            }
+            self.save_file = Some(save_file);
         }
+        Ok(())
     }
 
-    pub fn open_rom(&mut self, name: &str, boot: &str){
-        //let romName = *name;
-        let path = Path::new(name);
-        let display = path.display();
-        let mut file = match File::open(&path) {
-            Err(why) => panic!("couldn't open {}: {}", display, why.to_string()),
-            Ok(file) => file,
+    // write battery backed ram to the save file. it's written to a temporary file first so
+    // the old save survives if anything goes wrong
+    pub fn save(&mut self) -> io::Result<()> {
+        let save_file = match &self.save_file {
+            Some(save_file) => save_file,
+            None => return Ok(()),
         };
 
-        let mut rom_data = Vec::new();
-        file.read_to_end(&mut rom_data);
+        let temp_file = save_file.with_extension("sav.tmp");
+        fs::write(&temp_file, self.mapper.save_data())?;
+        fs::rename(&temp_file, save_file)?;
 
-        //this is probably incorrect idk
-        //self.cart = romData;
+        self.save_dirty = false;
+        self.save_idle = 0;
+        self.save_age = 0;
+        Ok(())
+    }
 
-        //load romData into memory
-        for i in 0..rom_data.len(){
-            self.memory[i] = rom_data[i];
+    // save if the game has written to cartridge ram and either stopped doing so for a while or
+    // has kept at it for long enough, meant to be called every frame
+    pub fn save_if_idle(&mut self) -> io::Result<()> {
+        if self.save_dirty && (self.save_idle >= SAVE_IDLE_CYCLES || self.save_age >= SAVE_MAX_AGE_CYCLES) {
+            self.save()
+        } else {
+            Ok(())
         }
+    }
 
-        for i in 0..rom_data.len(){
-            //print!("{:X}, ", rom_data[i]);
+    // load a boot rom and map it over the cartridge until the game unmaps it
+    pub fn open_boot_rom(&mut self, name: &str) -> Result<(), LoadError> {
+        let boot_data = read_file(Path::new(name))?;
+        let expected = if self.model.is_cgb() { 0x900 } else { 0x100 };
+        if boot_data.len() != expected {
+            return Err(LoadError::BootRomSize { expected, actual: boot_data.len() })
         }
+
+        self.boot_rom = boot_data;
+        self.boot_mapped = true;
+        Ok(())
+    }
+
+    // the boot rom byte at addr if it is currently mapped there. the cgb boot rom is larger and
+    // leaves a hole at 0100-01FF so the cartridge header stays readable
+    fn boot_rom_byte(&self, addr: u16) -> Option<u8> {
+        if !self.boot_mapped || (0x100..0x200).contains(&addr) {
+            return None
+        }
+        self.boot_rom.get(addr as usize).copied()
     }
 
     fn test(&mut self, x: u32) -> u32{
         x + 2
     }
 
-}
\ No newline at end of file
+}
+
+impl MMU {
+    // what's mapped at addr, without the cpu being locked out during OAM DMA
+    fn read_mapped(&self, addr: u16) -> u8 {
+        match addr {
+            0xff00 => self.joypad.read_reg(),
+            0xff01..=0xff02 => self.serial.read_reg(addr),
+            0xff04..=0xff07 => self.timer.read_reg(addr),
+            0xff0f => self.intr.read_if(),
+            0xff10..=0xff3f => self.apu.read_reg(addr),
+            0xff46 => self.dma.read_reg(),
+            0xff40..=0xff45 | 0xff47..=0xff4b => self.gpu.read_reg(addr),
+            // unused, including the write-only FF50
+            0xff03 | 0xff08..=0xff0e | 0xff4c..=0xff7f => 0xff,
+            0xffff => self.intr.read_ie(),
+            0x0000..=0x7fff => match self.boot_rom_byte(addr) {
+                Some(data) => data,
+                None => self.mapper.read_rom(addr),
+            },
+            0x8000..=0x9fff => self.gpu.read_vram(addr),
+            0xa000..=0xbfff => self.mapper.read_ram(addr),
+            0xc000..=0xfdff => self.wram[(addr as usize - 0xc000) % 0x2000],    // E000-FDFF echoes
+            0xfe00..=0xfe9f => self.gpu.read_oam(addr),
+            0xfea0..=0xfeff => self.unusable_area(addr),
+            0xff80..=0xfffe => self.hram[(addr - 0xff80) as usize],
+        }
+    }
+
+    // what reading FEA0-FEFF returns differs between models. the dmg family reads 0 unless the
+    // gpu is using OAM, newer cgbs and the agb repeat the upper nibble of the address
+    fn unusable_area(&self, addr: u16) -> u8 {
+        if self.model.is_cgb() {
+            let nibble = ((addr >> 4) & 0x0f) as u8;
+            nibble << 4 | nibble
+        } else if self.gpu.oam_locked() {
+            0xff
+        } else {
+            0x00
+        }
+    }
+
+    // copy the next byte of a running OAM DMA transfer
+    fn tick_dma(&mut self) {
+        if let Some((source, offset)) = self.dma.tick() {
+            let val = self.read_mapped(source);
+            self.gpu.write_oam(0xfe00 + offset as u16, val);
+        }
+    }
+}
+
+impl Bus for MMU {
+    // during OAM DMA the cpu can only reach FF00-FFFF, everything else reads 0xFF and ignores
+    // writes. the transfer takes over the buses to the cartridge, work ram, vram and OAM, but the
+    // io registers and IE sit on the cpu's own bus with high ram, so games can still poll the
+    // joypad or restart the transfer while it runs
+    fn read_byte(&mut self, addr: u16) -> u8 {
+        if self.dma.blocking() && addr < 0xff00 {
+            return 0xff
+        }
+        self.read_mapped(addr)
+    }
+
+    fn write_byte(&mut self, addr: u16, data: u8) {
+        if self.dma.blocking() && addr < 0xff00 {
+            return
+        }
+
+        match addr {
+            0xff00 => self.joypad.write_reg(data),
+            0xff01..=0xff02 => self.serial.write_reg(addr, data),
+            0xff04..=0xff07 => self.timer.write_reg(addr, data),
+            0xff0f => self.intr.write_if(data),
+            0xff10..=0xff3f => self.apu.write_reg(addr, data),
+            0xff46 => self.dma.write_reg(data),
+            0xff40..=0xff45 | 0xff47..=0xff4b => self.gpu.write_reg(addr, data),
+            // writing anything but 0 unmaps the boot rom for good
+            0xff50 => if data != 0 { self.boot_mapped = false; },
+            0xff03 | 0xff08..=0xff0e | 0xff4c..=0xff4f | 0xff51..=0xff7f => (),
+            0xffff => self.intr.write_ie(data),
+            0x0000..=0x7fff => self.mapper.write_rom(addr, data),
+            0xa000..=0xbfff => {
+                self.mapper.write_ram(addr, data);
+                if self.save_file.is_some() {
+                    self.save_dirty = true;
+                    self.save_idle = 0;
+                }
+            },
+            0x8000..=0x9fff => self.gpu.write_vram(addr, data),
+            // echo ram is the same work ram seen through E000-FDFF--see section 2.5.2
+            0xc000..=0xfdff => self.wram[(addr as usize - 0xc000) % 0x2000] = data,
+            0xfe00..=0xfe9f => self.gpu.write_oam(addr, data),
+            0xfea0..=0xfeff => (),
+            0xff80..=0xfffe => self.hram[(addr - 0xff80) as usize] = data,
+        }
+    }
+
+    fn tick(&mut self, cycles: u8) {
+        self.clock.tick(cycles as u32);
+        for _ in 0..cycles {
+            self.tick_dma();
+        }
+        self.timer.tick(cycles, &mut self.intr);
+        self.serial.tick(cycles, &mut self.intr);
+        self.mapper.tick(cycles);
+        if self.save_dirty {
+            self.save_idle = self.save_idle.saturating_add(cycles as u32);
+            self.save_age = self.save_age.saturating_add(cycles as u32);
+        }
+        self.gpu.step(cycles as u32 * 4, &mut self.intr);   // the gpu counts clocks, not machine cycles
+    }
+
+    fn bank(&self, addr: u16) -> u16 {
+        if addr < 0x8000 { self.mapper.rom_bank(addr) } else { 0 }
+    }
+
+    fn boot_rom_mapped(&self) -> bool {
+        self.boot_mapped
+    }
+
+    fn pending_interrupts(&mut self) -> u8 {
+        self.intr.pending()
+    }
+
+    fn ack_interrupt(&mut self, intr: Interrupt) {
+        self.intr.clear(intr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // an mbc1 rom with 8K of battery backed ram, in a directory of its own
+    fn battery_rom(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rustyboi-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut rom = vec![0; 0x8000];
+        rom[0x147] = 0x03;
+        rom[0x149] = 0x02;
+        rom[0x14d] = Cartridge::compute_header_checksum(&rom);
+        let path = dir.join("game.gb");
+        fs::write(&path, rom).unwrap();
+        path
+    }
+
+    fn open(path: &Path) -> MMU {
+        MMU::init(path.to_str().unwrap(), None, Model::Dmg).unwrap()
+    }
+
+    #[test]
+    fn dma_lockout() {
+        let mut mmu = open(&battery_rom("dma"));
+        mmu.write_byte(0xc000, 0x12);
+        mmu.write_byte(0xff80, 0x34);
+        mmu.write_byte(0xff46, 0xc0);
+        mmu.tick(2);
+
+        // only high ram, io and IE can be reached
+        assert_eq!(mmu.read_byte(0xc000), 0xff);
+        assert_eq!(mmu.read_byte(0x0147), 0xff);
+        mmu.write_byte(0xc001, 0x56);
+        mmu.write_byte(0xfe10, 0x56);
+        assert_eq!(mmu.read_byte(0xff80), 0x34);
+        mmu.write_byte(0xff81, 0x78);
+        assert_eq!(mmu.read_byte(0xff81), 0x78);
+        assert_eq!(mmu.read_byte(0xff46), 0xc0);
+        mmu.write_byte(0xffff, 0x1f);
+        assert_eq!(mmu.read_byte(0xffff), 0x1f);
+        mmu.write_byte(0xff0f, 0x04);
+        assert_eq!(mmu.read_byte(0xff0f) & 0x1f, 0x04);
+
+        mmu.tick(159);
+        assert_eq!(mmu.read_byte(0xc000), 0xff);
+        mmu.tick(1);
+        assert_eq!(mmu.read_byte(0xc000), 0x12);
+        assert_eq!(mmu.read_byte(0xc001), 0x00);
+        assert_eq!(mmu.gpu().read_oam(0xfe00), 0x12);
+        assert_eq!(mmu.gpu().read_oam(0xfe10), 0x00);
+    }
+
+    #[test]
+    fn save_is_renamed_into_place() {
+        let rom = battery_rom("save");
+        let save_file = rom.with_extension("sav");
+        let temp_file = rom.with_extension("sav.tmp");
+
+        // a temporary file left behind by an earlier crash doesn't get in the way
+        fs::write(&temp_file, [0xee; 4]).unwrap();
+
+        let mut mmu = open(&rom);
+        mmu.write_byte(0x0000, 0x0a);
+        mmu.write_byte(0xa123, 0x42);
+        mmu.save().unwrap();
+        assert!(!temp_file.exists());
+        let data = fs::read(&save_file).unwrap();
+        assert_eq!(data.len(), 0x2000);
+        assert_eq!(data[0x123], 0x42);
+
+        // loaded again on the next start
+        let mut mmu = open(&rom);
+        mmu.write_byte(0x0000, 0x0a);
+        assert_eq!(mmu.read_byte(0xa123), 0x42);
+
+        fs::remove_dir_all(rom.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn save_when_idle() {
+        let rom = battery_rom("idle");
+        let save_file = rom.with_extension("sav");
+
+        let mut mmu = open(&rom);
+        mmu.write_byte(0x0000, 0x0a);
+        mmu.write_byte(0xa000, 0x01);
+        mmu.save_if_idle().unwrap();
+        assert!(!save_file.exists());
+
+        for _ in 0..SAVE_IDLE_CYCLES {
+            mmu.tick(1);
+        }
+        mmu.save_if_idle().unwrap();
+        assert_eq!(fs::read(&save_file).unwrap()[0], 0x01);
+
+        fs::remove_dir_all(rom.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn no_save_without_battery() {
+        let rom = battery_rom("nobattery");
+        let mut data = fs::read(&rom).unwrap();
+        data[0x147] = 0x02;
+        data[0x14d] = Cartridge::compute_header_checksum(&data);
+        fs::write(&rom, data).unwrap();
+
+        let mut mmu = open(&rom);
+        mmu.write_byte(0x0000, 0x0a);
+        mmu.write_byte(0xa000, 0x01);
+        mmu.save().unwrap();
+        assert!(!rom.with_extension("sav").exists());
+
+        fs::remove_dir_all(rom.parent().unwrap()).unwrap();
+    }
+}
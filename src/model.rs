@@ -0,0 +1,37 @@
+// gameboy hardware revisions. they differ in the state their boot roms leave behind, which
+// games use to tell them apart (mostly through the A and B registers)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Model {
+    Dmg0,   // early japanese dmg with a different boot rom
+    Dmg,
+    Mgb,    // gameboy pocket and light
+    Sgb,
+    Sgb2,
+    Cgb,
+    Agb,    // gameboy advance running gameboy games
+}
+
+impl Model {
+    // parse a model name as given on the command line, e.g. "dmg" or "cgb"
+    pub fn from_name(name: &str) -> Option<Model> {
+        match name.to_ascii_lowercase().as_str() {
+            "dmg0" => Some(Model::Dmg0),
+            "dmg" => Some(Model::Dmg),
+            "mgb" => Some(Model::Mgb),
+            "sgb" => Some(Model::Sgb),
+            "sgb2" => Some(Model::Sgb2),
+            "cgb" => Some(Model::Cgb),
+            "agb" => Some(Model::Agb),
+            _ => None,
+        }
+    }
+
+    pub fn is_sgb(self) -> bool {
+        self == Model::Sgb || self == Model::Sgb2
+    }
+
+    // cgb and agb share the color boot rom and hardware
+    pub fn is_cgb(self) -> bool {
+        self == Model::Cgb || self == Model::Agb
+    }
+}
@@ -0,0 +1,49 @@
+// per-opcode metadata generated by build.rs from ops.txt, shared by the cpu, disassembler and tests
+
+// how an instruction affects one of the Z, N, H, C flags
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlagEffect {
+    Unchanged,
+    Reset,
+    Set,
+    Affected,   // depends on the result
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct OpInfo {
+    pub mnemonic: &'static str,
+    pub length: u8,             // bytes, including any 0xcb prefix
+    pub cycles: u8,             // machine cycles, or when a conditional branch is not taken
+    pub branch_cycles: u8,      // machine cycles when a conditional branch is taken
+    pub flags: [FlagEffect; 4], // Z, N, H, C
+}
+
+include!(concat!(env!("OUT_DIR"), "/opcodes.rs"));
+
+// metadata of the instruction starting with the given bytes, None for undefined opcodes
+pub fn op_info(bytes: &[u8]) -> Option<&'static OpInfo> {
+    match bytes {
+        [0xcb, op, ..] => Some(&CB_OPCODES[*op as usize]),
+        [op, ..] => OPCODES[*op as usize].as_ref(),
+        [] => None,
+    }
+}
+
+// format the instruction starting with the given bytes, filling in its immediate operand
+pub fn disassemble(bytes: &[u8]) -> String {
+    let info = match op_info(bytes) {
+        Some(info) => info,
+        None => return format!("DB ${:02X}", bytes.first().copied().unwrap_or(0)),
+    };
+
+    let byte = |i: usize| bytes.get(i).copied().unwrap_or(0);
+    let word = (byte(2) as u16) << 8 | byte(1) as u16;
+
+    info.mnemonic
+        .replace("d16", &format!("${:04X}", word))
+        .replace("a16", &format!("${:04X}", word))
+        .replace("d8", &format!("${:02X}", byte(1)))
+        .replace("a8", &format!("$FF{:02X}", byte(1)))
+        .replace("+r8", &format!("{:+}", byte(1) as i8))
+        .replace("r8", &format!("{:+}", byte(1) as i8))
+}
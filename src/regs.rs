@@ -1,3 +1,5 @@
+use crate::model::Model;
+
 pub struct Regs {
     a: u8,
     b: u8,
@@ -21,20 +23,59 @@ enum Flags {
 }
 
 impl Regs {
+    // power-on state, the boot rom sets everything up from here
     pub fn init() -> Regs {
-        // these are the values the regs are set to on startup (from manual)
         Regs {
-            a: 0x01,
-            b: 0x00,
-            c: 0x13,
-            d: 0x00,
-            e: 0xd8,
-            f: 0xb0,
-            h: 0x01,
-            l: 0x4d,
-            sp: 0xfffe,
-            pc: 0x0100,
-        }        
+            a: 0,
+            b: 0,
+            c: 0,
+            d: 0,
+            e: 0,
+            f: 0,
+            h: 0,
+            l: 0,
+            sp: 0,
+            pc: 0,
+        }
+    }
+
+    // values the regs are left with by the boot rom of each model (from manual and pan docs).
+    // some depend on the cartridge header at 0100-014F, given as header
+    pub fn post_boot(model: Model, header: &[u8; 0x50]) -> Regs {
+        let header_checksum = header[0x4d];
+        let cgb_mode = header[0x43] & 0x80 != 0;
+
+        let (a, f, b, c, d, e, h, l) = match model {
+            Model::Dmg0 => (0x01, 0x00, 0xff, 0x13, 0x00, 0xc1, 0x84, 0x03),
+            Model::Dmg | Model::Mgb => {
+                // H and C are set unless the header checksum is 0
+                let f = if header_checksum == 0 { 0x80 } else { 0xb0 };
+                let a = if model == Model::Mgb { 0xff } else { 0x01 };
+                (a, f, 0x00, 0x13, 0x00, 0xd8, 0x01, 0x4d)
+            },
+            Model::Sgb => (0x01, 0x00, 0x00, 0x14, 0x00, 0x00, 0xc0, 0x60),
+            Model::Sgb2 => (0xff, 0x00, 0x00, 0x14, 0x00, 0x00, 0xc0, 0x60),
+            Model::Cgb | Model::Agb if cgb_mode => (0x11, 0x80, 0x00, 0x00, 0xff, 0x56, 0x00, 0x0d),
+            Model::Cgb | Model::Agb => {
+                // the color boot rom picks a palette for old games from the sum of their
+                // title, but only if nintendo published them
+                let nintendo = header[0x4b] == 0x01 || (header[0x4b] == 0x33 && &header[0x44..0x46] == b"01");
+                let b = if nintendo { header[0x34..0x44].iter().fold(0u8, |sum, x| sum.wrapping_add(*x)) } else { 0 };
+                let (h, l) = if b == 0x43 || b == 0x58 { (0x99, 0x1a) } else { (0x00, 0x7c) };
+                (0x11, 0x80, b, 0x00, 0x00, 0x08, h, l)
+            },
+        };
+
+        let mut regs = Regs { a, b, c, d, e, f, h, l, sp: 0xfffe, pc: 0x0100 };
+
+        // the agb boot rom ends with an extra INC B
+        if model == Model::Agb {
+            regs.b = regs.b.wrapping_add(1);
+            regs.set_zflag(regs.b == 0);
+            regs.set_sflag(false);
+            regs.set_hflag(regs.b & 0x0f == 0);
+        }
+        regs
     }
 
     // get individual 8 bit reg values
@@ -44,7 +85,7 @@ impl Regs {
     pub fn d(&self) -> u8 { self.d }
     pub fn e(&self) -> u8 { self.e }
     pub fn f(&self) -> u8 { self.f }
-    pub fn h(&self) -> u8 { self.a }
+    pub fn h(&self) -> u8 { self.h }
     pub fn l(&self) -> u8 { self.l }
 
     // combine registers to get 16 bit reg values
@@ -69,7 +110,7 @@ impl Regs {
     pub fn set_c(&mut self, val: u8) { self.c = val; }
     pub fn set_d(&mut self, val: u8) { self.d = val; }
     pub fn set_e(&mut self, val: u8) { self.e = val; }
-    pub fn set_h(&mut self, val: u8) { self.a = val; }
+    pub fn set_h(&mut self, val: u8) { self.h = val; }
     pub fn set_l(&mut self, val: u8) { self.l = val; }
 
     // setting 16 bit reg conjunction values
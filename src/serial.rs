@@ -0,0 +1,69 @@
+use crate::interrupts::{Interrupt, Interrupts};
+
+// machine cycles to shift one bit out with the internal 8192 Hz clock
+const CYCLES_PER_BIT: u16 = 128;
+
+// SB (FF01) and SC (FF02). there is never anything on the other end of the link cable, so every
+// bit shifted in is a 1
+pub struct Serial {
+    data: u8,       // SB
+    control: u8,    // SC, bit 7 starts a transfer and bit 0 selects the internal clock
+    bits_left: u8,  // bits of the current transfer still to be shifted
+    cycles: u16,    // machine cycles into shifting the current bit
+}
+
+impl Serial {
+    pub fn init() -> Serial {
+        Serial {
+            data: 0,
+            control: 0,
+            bits_left: 0,
+            cycles: 0,
+        }
+    }
+
+    // with the external clock selected nothing drives the transfer, so it never finishes
+    fn transferring(&self) -> bool {
+        self.control & 0x81 == 0x81 && self.bits_left > 0
+    }
+
+    pub fn tick(&mut self, cycles: u8, intr: &mut Interrupts) {
+        if !self.transferring() {
+            return
+        }
+
+        self.cycles += cycles as u16;
+        while self.cycles >= CYCLES_PER_BIT && self.bits_left > 0 {
+            self.cycles -= CYCLES_PER_BIT;
+            self.data = (self.data << 1) | 1;
+            self.bits_left -= 1;
+        }
+
+        if self.bits_left == 0 {
+            self.control &= 0x7f;
+            intr.request(Interrupt::Serial);
+        }
+    }
+
+    pub fn read_reg(&self, addr: u16) -> u8 {
+        match addr {
+            0xff01 => self.data,
+            0xff02 => self.control | 0x7e,
+            _ => 0xff,
+        }
+    }
+
+    pub fn write_reg(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xff01 => self.data = val,
+            0xff02 => {
+                self.control = val & 0x81;
+                if val & 0x80 != 0 {
+                    self.bits_left = 8;
+                    self.cycles = 0;
+                }
+            },
+            _ => (),
+        }
+    }
+}
@@ -0,0 +1,214 @@
+// runs the per-opcode json tests from github.com/SingleStepTests/sm83 against the cpu.
+// each file (e.g. "8e.json" or "cb 1f.json") holds a list of tests like:
+//     {
+//         "name": "8e 0000",
+//         "initial": { "pc": 1, "sp": 2, "a": 3, ..., "ime": 0, "ram": [[addr, val], ...] },
+//         "final": { same as initial },
+//         "cycles": [[addr, val, "r-m"], [addr, val, "-wm"], [addr, null, "---"], ...]
+//     }
+// where every entry of cycles is one machine cycle and its bus activity.
+//
+// some releases of the tests model the overlapped fetch of the real cpu: the opcode was already
+// fetched before the test (pc points past it) and the last cycle fetches the next opcode. those
+// are detected from where the opcode named in the test sits in ram, and run by fetching it
+// ourselves first and prefetching the next one afterwards
+
+use crate::bus::{Access, Bus, BusAccess, FlatBus};
+use crate::cpu::CPU;
+use serde_json::Value;
+use std::any::Any;
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+
+const REGS: [&str; 10] = ["a", "b", "c", "d", "e", "f", "h", "l", "pc", "sp"];
+
+// run every test file in dir, printing each failing opcode with the differences of its first
+// failing test. returns whether everything passed
+fn run(dir: &Path) -> bool {
+    let mut files: Vec<_> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.extension() == Some(OsStr::new("json")))
+            .collect(),
+        Err(why) => { println!("couldn't read {}: {}", dir.display(), why); return false },
+    };
+    files.sort();
+
+    let mut failed_ops = 0;
+    for file in &files {
+        let tests = match fs::read_to_string(file).map(|text| serde_json::from_str::<Value>(&text)) {
+            Ok(Ok(Value::Array(tests))) => tests,
+            _ => { println!("{}: not a list of tests", file.display()); failed_ops += 1; continue },
+        };
+
+        let mut failures = 0;
+        let mut panicked = false;
+        for test in &tests {
+            // debug builds check every opcode against ops.txt and panic when it doesn't match.
+            // that is a failure of this opcode only, and the rest of its tests would panic the
+            // same way
+            let diffs = match panic::catch_unwind(AssertUnwindSafe(|| run_test(test))) {
+                Ok(diffs) => diffs,
+                Err(why) => {
+                    panicked = true;
+                    vec![format!("panicked: {}", panic_message(&*why))]
+                },
+            };
+            if !diffs.is_empty() {
+                if failures == 0 {
+                    println!("{}: {}", file.display(), test["name"].as_str().unwrap_or("?"));
+                    for diff in diffs {
+                        println!("    {}", diff);
+                    }
+                }
+                failures += 1;
+            }
+            if panicked {
+                break
+            }
+        }
+
+        if panicked {
+            println!("    the remaining tests weren't run");
+            failed_ops += 1;
+        } else if failures > 0 {
+            println!("    {}/{} tests failed", failures, tests.len());
+            failed_ops += 1;
+        }
+    }
+
+    println!("{}/{} opcodes passed", files.len() - failed_ops, files.len());
+    failed_ops == 0
+}
+
+// SST_DIR=../sm83/v1 cargo test --release sst -- --ignored
+#[test]
+#[ignore]
+fn single_step_tests() {
+    let dir = env::var("SST_DIR").expect("SST_DIR should name the directory of the json tests");
+    assert!(run(Path::new(&dir)));
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else {
+        "?"
+    }
+}
+
+fn num(val: &Value) -> u16 {
+    val.as_u64().unwrap_or(0) as u16
+}
+
+// run a single test, returning a description of every mismatch
+fn run_test(test: &Value) -> Vec<String> {
+    let initial = &test["initial"];
+    let expected = &test["final"];
+
+    let mut bus = FlatBus::init();
+    for entry in initial["ram"].as_array().into_iter().flatten() {
+        bus.load(num(&entry[0]), &[num(&entry[1]) as u8]);
+    }
+    if let Some(ie) = initial["ie"].as_u64() {
+        bus.load(0xffff, &[ie as u8]);
+    }
+
+    let pc = num(&initial["pc"]);
+    let opcode = test["name"].as_str()
+        .and_then(|name| name.split_whitespace().next())
+        .and_then(|op| u8::from_str_radix(op, 16).ok());
+    let prefetched = opcode.is_some()
+        && opcode != Some(bus.read_byte(pc))
+        && opcode == Some(bus.read_byte(pc.wrapping_sub(1)));
+    bus.enable_trace();
+
+    let mut cpu = CPU::with_bus(bus);
+    {
+        let regs = cpu.regs_mut();
+        regs.set_af((num(&initial["a"]) << 8) | num(&initial["f"]));
+        regs.set_bc((num(&initial["b"]) << 8) | num(&initial["c"]));
+        regs.set_de((num(&initial["d"]) << 8) | num(&initial["e"]));
+        regs.set_hl((num(&initial["h"]) << 8) | num(&initial["l"]));
+        regs.set_pc(if prefetched { pc.wrapping_sub(1) } else { pc });
+        regs.set_sp(num(&initial["sp"]));
+    }
+    cpu.set_ime(num(&initial["ime"]) != 0);
+
+    let cycles = match cpu.step() {
+        Ok(step) => step.cycles,
+        Err(fault) => return vec![fault.to_string()],
+    };
+    if prefetched {
+        let next_pc = cpu.regs().pc();
+        cpu.bus_mut().read_byte(next_pc);
+        cpu.bus_mut().tick(1);
+        cpu.regs_mut().set_pc(next_pc.wrapping_add(1));
+    }
+    let mut diffs = Vec::new();
+
+    // registers
+    let regs = cpu.regs();
+    let actual = [
+        regs.a() as u16, regs.b() as u16, regs.c() as u16, regs.d() as u16, regs.e() as u16,
+        regs.af() & 0xff, regs.h() as u16, regs.l() as u16, regs.pc(), regs.sp(),
+    ];
+    for (name, val) in REGS.iter().zip(actual.iter().copied()) {
+        let want = num(&expected[*name]);
+        if want != val {
+            diffs.push(format!("{}: expected {:#06x}, got {:#06x}", name, want, val));
+        }
+    }
+    if expected["ime"].is_u64() && (num(&expected["ime"]) != 0) != cpu.ime() {
+        diffs.push(format!("ime: expected {}, got {}", num(&expected["ime"]), cpu.ime() as u8));
+    }
+
+    // memory, the trace is copied first so reading it back doesn't show up as bus activity
+    let mut trace = cpu.bus().trace().to_vec();
+    if prefetched && !trace.is_empty() {
+        trace.remove(0);
+    }
+    for entry in expected["ram"].as_array().into_iter().flatten() {
+        let addr = num(&entry[0]);
+        let want = num(&entry[1]) as u8;
+        let val = cpu.bus_mut().read_byte(addr);
+        if want != val {
+            diffs.push(format!("({:#06x}): expected {:#04x}, got {:#04x}", addr, want, val));
+        }
+    }
+
+    // bus activity, one entry per machine cycle
+    let expected_cycles: Vec<&Value> = test["cycles"].as_array().into_iter().flatten().collect();
+    if expected_cycles.len() != cycles as usize {
+        diffs.push(format!("cycles: expected {}, got {}", expected_cycles.len(), cycles));
+    }
+
+    let expected_accesses: Vec<Option<BusAccess>> = expected_cycles.iter().map(|c| {
+        let kind = match c[2].as_str() {
+            Some(k) if k.starts_with('r') => Access::Read,
+            Some(k) if k.contains('w') => Access::Write,
+            _ => return None,
+        };
+        Some(BusAccess { addr: num(&c[0]), data: num(&c[1]) as u8, kind })
+    }).collect();
+
+    if expected_accesses != trace {
+        diffs.push(format!("bus: expected {}", format_accesses(&expected_accesses)));
+        diffs.push(format!("     got      {}", format_accesses(&trace)));
+    }
+
+    diffs
+}
+
+fn format_accesses(accesses: &[Option<BusAccess>]) -> String {
+    accesses.iter().map(|a| match a {
+        Some(BusAccess { addr, data, kind: Access::Read }) => format!("r({:#06x})={:#04x}", addr, data),
+        Some(BusAccess { addr, data, kind: Access::Write }) => format!("w({:#06x})={:#04x}", addr, data),
+        None => "----".to_string(),
+    }).collect::<Vec<_>>().join(" ")
+}
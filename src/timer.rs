@@ -0,0 +1,87 @@
+use crate::interrupts::{Interrupt, Interrupts};
+
+// DIV, TIMA, TMA and TAC. DIV is the upper byte of a 16 bit counter running at the cpu clock,
+// and TIMA counts the falling edges of one of its bits selected by TAC
+pub struct Timer {
+    counter: u16,           // internal divider, incremented every clock (4 per machine cycle)
+    tima: u8,               // FF05
+    tma: u8,                // FF06
+    tac: u8,                // FF07
+    overflowed: bool,       // TIMA overflowed last cycle, reload and interrupt are due this cycle
+}
+
+impl Timer {
+    pub fn init() -> Timer {
+        Timer {
+            counter: 0,
+            tima: 0,
+            tma: 0,
+            tac: 0,
+            overflowed: false,
+        }
+    }
+
+    // set the internal divider, for starting in the state a boot rom leaves behind
+    pub fn set_counter(&mut self, counter: u16) {
+        self.counter = counter;
+    }
+
+    // the divider bit TIMA counts the falling edges of, anded with the timer enable
+    fn signal(&self) -> bool {
+        let bit = match self.tac & 0b11 {
+            0b00 => 9,  // 4096 Hz
+            0b01 => 3,  // 262144 Hz
+            0b10 => 5,  // 65536 Hz
+            _ => 7,     // 16384 Hz
+        };
+        self.tac & 0b100 != 0 && self.counter & (1 << bit) != 0
+    }
+
+    // run the given change to the divider or TAC, incrementing TIMA if it made the signal fall.
+    // this is why writing DIV or TAC can increment TIMA
+    fn update(&mut self, change: impl FnOnce(&mut Timer)) {
+        let before = self.signal();
+        change(self);
+        if before && !self.signal() {
+            let (tima, overflow) = self.tima.overflowing_add(1);
+            self.tima = tima;
+            self.overflowed = overflow;
+        }
+    }
+
+    pub fn tick(&mut self, cycles: u8, intr: &mut Interrupts) {
+        for _ in 0..cycles {
+            // TIMA reads 0 for a cycle after overflowing before TMA is reloaded
+            if self.overflowed {
+                self.overflowed = false;
+                self.tima = self.tma;
+                intr.request(Interrupt::Timer);
+            }
+            self.update(|t| t.counter = t.counter.wrapping_add(4));
+        }
+    }
+
+    pub fn read_reg(&self, addr: u16) -> u8 {
+        match addr {
+            0xff04 => (self.counter >> 8) as u8,
+            0xff05 => self.tima,
+            0xff06 => self.tma,
+            0xff07 => self.tac | 0xf8,
+            _ => 0xff,
+        }
+    }
+
+    pub fn write_reg(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xff04 => self.update(|t| t.counter = 0),   // any write resets the whole divider
+            0xff05 => {
+                // writing TIMA in the cycle it overflowed cancels the reload and interrupt
+                self.tima = val;
+                self.overflowed = false;
+            },
+            0xff06 => self.tma = val,
+            0xff07 => self.update(|t| t.tac = val & 0x07),
+            _ => (),
+        }
+    }
+}